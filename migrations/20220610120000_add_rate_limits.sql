@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS rate_limits (
+    ip INET NOT NULL,
+    action VARCHAR(32) NOT NULL,
+    PRIMARY KEY (ip, action),
+    last_hit TIMESTAMP NOT NULL DEFAULT NOW()
+);
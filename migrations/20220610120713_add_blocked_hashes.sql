@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS blocked_hashes (
+    hash UUID PRIMARY KEY NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,3 @@
+ALTER TABLE IF EXISTS images
+    ADD COLUMN IF NOT EXISTS
+        size BIGINT NOT NULL DEFAULT 0;
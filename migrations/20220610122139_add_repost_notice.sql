@@ -0,0 +1,3 @@
+ALTER TABLE IF EXISTS boards
+    ADD COLUMN IF NOT EXISTS
+        repost_notice BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,5 @@
+ALTER TABLE IF EXISTS posts
+    ADD COLUMN IF NOT EXISTS
+        is_sticky BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN IF NOT EXISTS
+        sticky_until TIMESTAMP;
@@ -0,0 +1,3 @@
+ALTER TABLE IF EXISTS boards
+    ADD COLUMN IF NOT EXISTS
+        sage_stops_bump BOOLEAN NOT NULL DEFAULT TRUE;
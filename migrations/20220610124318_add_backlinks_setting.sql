@@ -0,0 +1,3 @@
+ALTER TABLE IF EXISTS boards
+    ADD COLUMN IF NOT EXISTS
+        backlinks BOOLEAN NOT NULL DEFAULT TRUE;
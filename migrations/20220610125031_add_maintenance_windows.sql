@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS maintenance_windows (
+    id SERIAL PRIMARY KEY,
+    -- 0 is Sunday, NULL repeats the window every day
+    weekday SMALLINT CHECK (weekday BETWEEN 0 AND 6),
+    starts_at TIME NOT NULL,
+    duration INTERVAL NOT NULL
+);
@@ -0,0 +1,3 @@
+ALTER TABLE IF EXISTS boards
+    ADD COLUMN IF NOT EXISTS
+        require_content_for_op BOOLEAN NOT NULL DEFAULT FALSE;
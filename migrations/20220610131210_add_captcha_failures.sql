@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS captcha_failures (
+    ip INET PRIMARY KEY NOT NULL,
+    failures INTEGER NOT NULL DEFAULT 0,
+    last_failure TIMESTAMP NOT NULL DEFAULT NOW()
+);
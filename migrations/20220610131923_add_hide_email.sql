@@ -0,0 +1,3 @@
+ALTER TABLE IF EXISTS boards
+    ADD COLUMN IF NOT EXISTS
+        hide_email BOOLEAN NOT NULL DEFAULT FALSE;
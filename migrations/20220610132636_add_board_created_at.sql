@@ -0,0 +1,3 @@
+ALTER TABLE IF EXISTS boards
+    ADD COLUMN IF NOT EXISTS
+        created_at TIMESTAMP NOT NULL DEFAULT NOW();
@@ -0,0 +1,7 @@
+ALTER TABLE IF EXISTS posts
+    ADD COLUMN IF NOT EXISTS
+        locked BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE IF EXISTS boards
+    ADD COLUMN IF NOT EXISTS
+        autolock_after_days INTEGER;
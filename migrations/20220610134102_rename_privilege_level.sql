@@ -0,0 +1 @@
+ALTER TYPE privelege_level RENAME TO privilege_level;
@@ -0,0 +1,7 @@
+CREATE TYPE captcha_scope AS ENUM (
+    'none',
+    'threads',
+    'all'
+);
+
+ALTER TABLE boards ADD COLUMN captcha_scope captcha_scope NOT NULL DEFAULT 'all';
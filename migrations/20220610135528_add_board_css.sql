@@ -0,0 +1 @@
+ALTER TABLE boards ADD COLUMN custom_css TEXT;
@@ -0,0 +1 @@
+ALTER TABLE boards ADD COLUMN text_only BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,3 @@
+-- The old column never had a defined format, so existing users need their password set again.
+ALTER TABLE users DROP COLUMN password;
+ALTER TABLE users ADD COLUMN password_hash TEXT;
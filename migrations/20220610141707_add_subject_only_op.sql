@@ -0,0 +1 @@
+ALTER TABLE boards ADD COLUMN subject_only_op BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1 @@
+ALTER TABLE images ADD COLUMN thumb_ext VARCHAR(8) NOT NULL DEFAULT 'png';
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS reports (
+    id SERIAL PRIMARY KEY,
+    board VARCHAR(255) NOT NULL,
+    post_id INTEGER NOT NULL,
+    FOREIGN KEY (post_id, board) REFERENCES posts(id, board),
+    ip INET NOT NULL,
+    reason VARCHAR(200) NOT NULL,
+    count INTEGER NOT NULL DEFAULT 1,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    dismissed BOOLEAN NOT NULL DEFAULT FALSE,
+    UNIQUE (board, post_id, ip)
+);
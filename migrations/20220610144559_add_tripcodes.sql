@@ -0,0 +1 @@
+ALTER TABLE posts ADD COLUMN tripcode VARCHAR(10);
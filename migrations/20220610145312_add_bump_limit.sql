@@ -0,0 +1 @@
+ALTER TABLE boards ADD COLUMN bump_limit INTEGER;
@@ -0,0 +1 @@
+ALTER TABLE images ADD COLUMN media_type VARCHAR(32);
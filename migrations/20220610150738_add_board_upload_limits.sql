@@ -0,0 +1,2 @@
+ALTER TABLE boards ADD COLUMN max_upload_bytes BIGINT;
+ALTER TABLE boards ADD COLUMN allowed_mime_types TEXT[];
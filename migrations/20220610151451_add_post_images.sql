@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS post_images (
+    board VARCHAR(255) NOT NULL,
+    post_id INTEGER NOT NULL,
+    FOREIGN KEY (post_id, board) REFERENCES posts(id, board),
+    position SMALLINT NOT NULL,
+    image UUID NOT NULL REFERENCES images(hash),
+    PRIMARY KEY (board, post_id, position)
+);
+
+INSERT INTO post_images(board, post_id, position, image)
+    SELECT board, id, 0, image FROM posts WHERE image IS NOT NULL;
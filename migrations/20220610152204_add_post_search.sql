@@ -0,0 +1,4 @@
+-- Indexed as an expression rather than a stored column so that `SELECT *` on posts stays
+-- mappable to `Post`. Queries must use the exact same expression to hit the index.
+CREATE INDEX IF NOT EXISTS posts_search_idx ON posts
+    USING GIN (to_tsvector('simple', COALESCE(plaintext_content, '')));
@@ -0,0 +1,4 @@
+-- Images are now named after the first half of their SHA-256 digest, with the full digest kept
+-- here to tell collisions apart. Older images keep their MD5 names and have no digest, so files
+-- don't need renaming, but new uploads of the same content are stored again under the new name.
+ALTER TABLE images ADD COLUMN sha256 BYTEA;
@@ -0,0 +1,2 @@
+ALTER TABLE boards ADD COLUMN description TEXT;
+ALTER TABLE boards ADD COLUMN description_html TEXT;
@@ -0,0 +1 @@
+ALTER TABLE boards ADD COLUMN max_threads INTEGER;
@@ -0,0 +1 @@
+ALTER TABLE posts ADD COLUMN archived BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+-- SHA-256 of the random token in the poster's cookie, for marking their own posts.
+ALTER TABLE posts ADD COLUMN poster BYTEA;
@@ -0,0 +1,2 @@
+-- Posting cooldowns look up each IP's latest post.
+CREATE INDEX IF NOT EXISTS posts_ip_posted_at_idx ON posts (ip, posted_at DESC);
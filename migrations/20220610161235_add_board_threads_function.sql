@@ -0,0 +1,31 @@
+-- The live threads of a board, numbered in the order the board lists them: stickies first,
+-- then by last bump. Bumps stop counting past `bump_limit` posts, and saged replies don't
+-- bump if `sage_stops_bump` is set. Both the board listing and the thread archiver go by this,
+-- so a thread is archived exactly when it's fallen off the end of the board.
+CREATE OR REPLACE FUNCTION board_threads(
+    board_name VARCHAR,
+    sage_stops_bump BOOLEAN,
+    bump_limit INTEGER
+) RETURNS TABLE (id INTEGER, position BIGINT) AS $$
+    WITH numbered AS (
+        SELECT thread, id, sage, posted_at,
+            ROW_NUMBER() OVER (PARTITION BY thread ORDER BY posted_at, id) AS n
+        FROM posts
+        WHERE board = $1 AND deleted_at IS NULL
+    ), bumps AS (
+        SELECT thread, MAX(posted_at) AS last_bump
+        FROM numbered
+        WHERE (thread = id OR NOT sage OR NOT $2)
+            AND ($3 IS NULL OR n <= $3)
+        GROUP BY thread
+    )
+    SELECT posts.id, ROW_NUMBER() OVER (
+        ORDER BY
+            posts.is_sticky AND (posts.sticky_until IS NULL OR posts.sticky_until > NOW()) DESC,
+            bumps.last_bump DESC,
+            posts.id DESC
+    )
+    FROM posts
+        JOIN bumps ON posts.id = bumps.thread
+    WHERE posts.board = $1 AND posts.deleted_at IS NULL AND NOT posts.archived
+$$ LANGUAGE SQL STABLE;
@@ -0,0 +1,348 @@
+use image::ImageFormat;
+use once_cell::sync::Lazy;
+use rocket::http::SameSite;
+use std::{env, fmt::Debug, ops::Deref, path::PathBuf, str::FromStr, time::Duration};
+
+pub static CONFIG: Lazy<Config> = Lazy::new(Config::from_env);
+
+/// Site-wide settings, read from the environment (or `.env`) on first use.
+pub struct Config {
+    pub rate_limit_backend: RateLimitBackend,
+    pub post_cooldown: Duration,
+    pub thumb_max_aspect: f32,
+    pub thumb_fit: ThumbFit,
+    pub linkify_boards: bool,
+    pub cookie_same_site: SameSitePolicy,
+    pub cookie_secure: bool,
+    pub max_boards: Option<i64>,
+    pub storage_quota_bytes: Option<i64>,
+    pub slow_query: Option<Duration>,
+    pub fix_orientation: bool,
+    pub invisible_chars: InvisibleChars,
+    pub allowed_image_formats: ImageFormats,
+    pub images_dir: PathBuf,
+    pub thumbs_dir: PathBuf,
+    pub title_max_chars: usize,
+    pub captcha_max_failures: i32,
+    pub captcha_lockout: Duration,
+    pub stats_window_days: i32,
+    pub empty_board_ttl_days: Option<i32>,
+    pub board_cleanup_interval: Duration,
+    pub preview_max_chars: usize,
+    pub max_uploads_per_ip: usize,
+    pub inline_thumb_max_bytes: Option<u64>,
+    pub detailed_errors: bool,
+    pub reply_depth: usize,
+    pub captcha_check_cooldown: Duration,
+    pub custom_css_max_bytes: usize,
+    pub prune_interval: Duration,
+    pub captcha_ttl: Duration,
+    pub session_ttl: Duration,
+    pub author_max_chars: usize,
+    pub prefix_post_ids: bool,
+    pub honeypot: Honeypot,
+    pub honeypot_field: String,
+    pub ip_hash_salt: Option<String>,
+    pub thumb_format: ThumbFormat,
+    pub thumb_jpeg_quality: u8,
+    pub max_reply_links: usize,
+    pub threads_per_page: i64,
+    pub ffmpeg: Option<PathBuf>,
+    pub max_upload_bytes: u64,
+    pub thumb_max_px: u32,
+    pub max_images_per_post: usize,
+    pub site_url: String,
+    pub thread_cooldown: Duration,
+    pub preview_replies: i64,
+    pub captcha_pass: Option<Duration>,
+    pub storage_backend: StorageBackend,
+    pub s3_bucket: Option<String>,
+    pub s3_region: String,
+    pub s3_endpoint: Option<String>,
+    pub s3_access_key: Option<String>,
+    pub s3_secret_key: Option<String>,
+    pub s3_public_url: Option<String>,
+    pub subject_max_chars: usize,
+    pub email_max_chars: usize,
+    pub content_max_chars: usize,
+    pub board_title_max_chars: usize,
+    pub board_description_max_chars: usize,
+}
+
+impl Config {
+    fn from_env() -> Self {
+        Self {
+            rate_limit_backend: var("RATE_LIMIT_BACKEND", RateLimitBackend::Memory),
+            post_cooldown: Duration::from_secs(var("POST_COOLDOWN_SECS", 15)),
+            thumb_max_aspect: var("THUMB_MAX_ASPECT", 4.0),
+            thumb_fit: var("THUMB_FIT", ThumbFit::Crop),
+            linkify_boards: var("LINKIFY_BOARDS", false),
+            cookie_same_site: var("COOKIE_SAME_SITE", SameSitePolicy::Lax),
+            cookie_secure: var("COOKIE_SECURE", true),
+            max_boards: opt_var("MAX_BOARDS"),
+            storage_quota_bytes: opt_var("STORAGE_QUOTA_BYTES"),
+            slow_query: opt_var("SLOW_QUERY_MS").map(Duration::from_millis),
+            fix_orientation: var("FIX_ORIENTATION", true),
+            invisible_chars: var("INVISIBLE_CHARS", InvisibleChars::Strip),
+            allowed_image_formats: var(
+                "ALLOWED_IMAGE_FORMATS",
+                ImageFormats(vec![ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::Gif]),
+            ),
+            images_dir: var("IMAGES_DIR", PathBuf::from("./images")),
+            thumbs_dir: var("THUMBS_DIR", PathBuf::from("./thumbs")),
+            title_max_chars: var("TITLE_MAX_CHARS", 50),
+            captcha_max_failures: var("CAPTCHA_MAX_FAILURES", 5),
+            captcha_lockout: Duration::from_secs(var("CAPTCHA_LOCKOUT_SECS", 600)),
+            stats_window_days: var("STATS_WINDOW_DAYS", 30),
+            empty_board_ttl_days: opt_var("EMPTY_BOARD_TTL_DAYS"),
+            board_cleanup_interval: Duration::from_secs(var("BOARD_CLEANUP_INTERVAL_SECS", 3600)),
+            preview_max_chars: var("PREVIEW_MAX_CHARS", 1500),
+            max_uploads_per_ip: var("MAX_UPLOADS_PER_IP", 2),
+            inline_thumb_max_bytes: opt_var("INLINE_THUMB_MAX_BYTES"),
+            detailed_errors: var("DETAILED_ERRORS", cfg!(debug_assertions)),
+            reply_depth: var("REPLY_DEPTH", 1),
+            captcha_check_cooldown: Duration::from_secs(var("CAPTCHA_CHECK_COOLDOWN_SECS", 2)),
+            custom_css_max_bytes: var("CUSTOM_CSS_MAX_BYTES", 16 * 1024),
+            prune_interval: Duration::from_secs(var("PRUNE_INTERVAL_SECS", 600)),
+            captcha_ttl: Duration::from_secs(var("CAPTCHA_TTL_SECS", 3600)),
+            session_ttl: Duration::from_secs(var("SESSION_TTL_SECS", 30 * 24 * 3600)),
+            author_max_chars: var("AUTHOR_MAX_CHARS", 32),
+            prefix_post_ids: var("PREFIX_POST_IDS", false),
+            honeypot: var("HONEYPOT", Honeypot::Reject),
+            honeypot_field: var("HONEYPOT_FIELD", "website".to_string()),
+            ip_hash_salt: opt_var("IP_HASH_SALT"),
+            thumb_format: var("THUMB_FORMAT", ThumbFormat::Auto),
+            thumb_jpeg_quality: var("THUMB_JPEG_QUALITY", 85),
+            max_reply_links: var("MAX_REPLY_LINKS", 50),
+            threads_per_page: var("THREADS_PER_PAGE", 10),
+            ffmpeg: opt_var("FFMPEG_PATH"),
+            max_upload_bytes: var("MAX_UPLOAD_BYTES", 10 * 1024 * 1024),
+            thumb_max_px: var("THUMB_MAX_PX", 200),
+            max_images_per_post: var("MAX_IMAGES_PER_POST", 5),
+            site_url: var("SITE_URL", "http://localhost:8000".to_string()),
+            thread_cooldown: Duration::from_secs(var("THREAD_COOLDOWN_SECS", 60)),
+            preview_replies: var("PREVIEW_REPLIES", 3),
+            captcha_pass: opt_var("CAPTCHA_PASS_SECS").map(Duration::from_secs),
+            storage_backend: var("STORAGE_BACKEND", StorageBackend::Local),
+            s3_bucket: opt_var("S3_BUCKET"),
+            s3_region: var("S3_REGION", "us-east-1".to_string()),
+            s3_endpoint: opt_var("S3_ENDPOINT"),
+            s3_access_key: opt_var("S3_ACCESS_KEY"),
+            s3_secret_key: opt_var("S3_SECRET_KEY"),
+            s3_public_url: opt_var("S3_PUBLIC_URL"),
+            subject_max_chars: var("SUBJECT_MAX_CHARS", 128),
+            email_max_chars: var("EMAIL_MAX_CHARS", 64),
+            content_max_chars: var("CONTENT_MAX_CHARS", 10000),
+            board_title_max_chars: var("BOARD_TITLE_MAX_CHARS", 64),
+            board_description_max_chars: var("BOARD_DESCRIPTION_MAX_CHARS", 2000),
+        }
+    }
+}
+
+fn var<T>(key: &str, default: T) -> T
+where
+    T: FromStr,
+    T::Err: Debug,
+{
+    opt_var(key).unwrap_or(default)
+}
+
+fn opt_var<T>(key: &str) -> Option<T>
+where
+    T: FromStr,
+    T::Err: Debug,
+{
+    env::var(key).ok().map(|v| {
+        v.parse()
+            .unwrap_or_else(|e| panic!("Invalid value for {key}: {e:?}"))
+    })
+}
+
+/// Where per-IP rate limit state is kept, which only covers captcha checks.
+/// Posting cooldowns go by the `posts` table instead, so they hold across instances either way.
+/// `memory` is fast but local to the process, `postgres` is shared between instances.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitBackend {
+    Memory,
+    Postgres,
+}
+
+impl FromStr for RateLimitBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory" => Ok(Self::Memory),
+            "postgres" => Ok(Self::Postgres),
+            _ => Err(format!("unknown rate limit backend `{s}`")),
+        }
+    }
+}
+
+/// Where uploads and thumbnails are kept.
+/// `local` uses `IMAGES_DIR` and `THUMBS_DIR`, `s3` an S3 compatible bucket shared between instances.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageBackend {
+    Local,
+    S3,
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(Self::Local),
+            "s3" => Ok(Self::S3),
+            _ => Err(format!("unknown storage backend `{s}`")),
+        }
+    }
+}
+
+/// What to do with thumbnails whose sides differ by more than `THUMB_MAX_ASPECT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbFit {
+    /// Cut the long side down around the center.
+    Crop,
+    /// Keep the whole image and pad the short side with transparency.
+    Pad,
+    /// Leave the aspect ratio alone.
+    None,
+}
+
+impl FromStr for ThumbFit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "crop" => Ok(Self::Crop),
+            "pad" => Ok(Self::Pad),
+            "none" => Ok(Self::None),
+            _ => Err(format!("unknown thumbnail fit `{s}`")),
+        }
+    }
+}
+
+/// What format thumbnails are saved in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbFormat {
+    /// PNG for images with transparency, JPEG for everything else.
+    Auto,
+    Png,
+    Jpeg,
+}
+
+impl FromStr for ThumbFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "png" => Ok(Self::Png),
+            "jpeg" => Ok(Self::Jpeg),
+            _ => Err(format!("unknown thumbnail format `{s}`")),
+        }
+    }
+}
+
+/// `SameSite` attribute put on the session and captcha cookies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSitePolicy {
+    Strict,
+    Lax,
+}
+
+impl FromStr for SameSitePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(Self::Strict),
+            "lax" => Ok(Self::Lax),
+            _ => Err(format!("unknown SameSite policy `{s}`")),
+        }
+    }
+}
+
+impl From<SameSitePolicy> for SameSite {
+    fn from(policy: SameSitePolicy) -> Self {
+        match policy {
+            SameSitePolicy::Strict => SameSite::Strict,
+            SameSitePolicy::Lax => SameSite::Lax,
+        }
+    }
+}
+
+/// What to do with zero-width and control characters in post names, titles and content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvisibleChars {
+    Strip,
+    Reject,
+    Allow,
+}
+
+impl FromStr for InvisibleChars {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strip" => Ok(Self::Strip),
+            "reject" => Ok(Self::Reject),
+            "allow" => Ok(Self::Allow),
+            _ => Err(format!("unknown invisible character policy `{s}`")),
+        }
+    }
+}
+
+/// What to do with posts that filled in the post form's hidden field, `HONEYPOT_FIELD`.
+/// It should look like something a bot would fill in, and not be the name of another field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Honeypot {
+    /// Don't put the field on the form at all.
+    Off,
+    /// Fail the post with an error.
+    Reject,
+    /// Pretend the post went through.
+    Drop,
+}
+
+impl FromStr for Honeypot {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "reject" => Ok(Self::Reject),
+            "drop" => Ok(Self::Drop),
+            _ => Err(format!("unknown honeypot policy `{s}`")),
+        }
+    }
+}
+
+/// Comma separated list of image formats by extension, e.g. `png,jpg,gif`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageFormats(pub Vec<ImageFormat>);
+
+impl FromStr for ImageFormats {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|ext| !ext.is_empty())
+            .map(|ext| {
+                ImageFormat::from_extension(ext)
+                    .ok_or_else(|| format!("unknown image format `{ext}`"))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl Deref for ImageFormats {
+    type Target = [ImageFormat];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
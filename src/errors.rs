@@ -1,10 +1,15 @@
-use std::io::Cursor;
+use std::{io::Cursor, time::Duration};
 
+use crate::config::CONFIG;
+use crate::routes::public::{footer, head};
+use maud::html;
 use rocket::{
+    catch,
     http::{ContentType, Status},
     response::{self, Responder},
     Request, Response,
 };
+use sqlx::types::time::PrimitiveDateTime;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -17,7 +22,7 @@ pub enum Error {
     Rocket(#[from] rocket::Error),
     #[error("{0}")]
     Dotenv(#[from] dotenv::Error),
-    #[error("404: NotFound")]
+    #[error("There's nothing here")]
     NotFound,
     #[error("{0}")]
     Io(#[from] std::io::Error),
@@ -27,11 +32,110 @@ pub enum Error {
     MissingImage,
     #[error("You're supposed to have a captcha cookie to do that")]
     MissingOrInvalidCaptchaID,
+    #[error("You're posting too fast, slow down")]
+    TooFast,
+    #[error("This file is not allowed")]
+    BlockedContent,
+    #[error("This site can't have any more boards")]
+    BoardLimitReached,
+    #[error("Board names must be 1 to 16 letters and digits, and not one the site uses itself")]
+    InvalidBoardName,
+    #[error("There's already a board with that name")]
+    BoardExists,
+    #[error("This site is out of space for new images")]
+    StorageQuotaExceeded,
+    #[error("Posting is disabled for maintenance until {}", .0.format("%Y-%m-%d %H:%M"))]
+    ReadOnly(PrimitiveDateTime),
+    #[error("Your post contains invisible or control characters")]
+    InvisibleCharacters,
+    #[error("This file type isn't supported")]
+    UnsupportedImageType,
+    #[error("This thread has reached its image limit, you can still reply without one")]
+    ThreadImageLimit,
+    #[error("You must write something when creating a thread")]
+    MissingContent,
+    #[error("Too many wrong captchas, try again later")]
+    CaptchaLockout,
+    #[error("This thread is locked")]
+    ThreadLocked,
+    #[error("This thread is archived")]
+    ThreadArchived,
+    #[error("This stylesheet can't be used: {0}")]
+    InvalidCss(&'static str),
+    #[error("Names can be at most {} characters long", CONFIG.author_max_chars)]
+    AuthorTooLong,
+    #[error("This board doesn't take images")]
+    ImagesDisabled,
+    #[error("Report reasons can be at most 200 characters long")]
+    ReportReasonTooLong,
+    #[error("Your post was rejected")]
+    Rejected,
+    #[error("Wrong name or password")]
+    InvalidCredentials,
+    #[error("{0}")]
+    PasswordHash(argon2::password_hash::Error),
+    #[error("This video couldn't be read")]
+    InvalidVideo,
+    #[error("This file is too large")]
+    UploadTooLarge,
+    #[error("This board doesn't take this type of file")]
+    DisallowedFileType,
+    #[error("Posts can have at most {} images", CONFIG.max_images_per_post)]
+    TooManyImages,
+    #[error("You're posting too fast, try again in {} seconds", retry_after(.0))]
+    Flood(Duration),
+    #[error("{0}")]
+    Storage(#[from] s3::error::S3Error),
+    #[error("This ban can't be made: {0}")]
+    InvalidBan(&'static str),
+    #[error("This file can't be stored")]
+    HashCollision,
+    #[error("Type the board's name to confirm deleting it")]
+    ConfirmationMismatch,
+    #[error("Too many people are following this thread, try again later")]
+    TooManyFollowers,
+    #[error("{0} can be at most {1} characters long")]
+    TooLong(&'static str, usize),
 }
 
-impl<'r, 'o: 'r> Responder<'r, 'o> for Error {
-    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'o> {
-        let status = match self {
+/// The page shown for errors, both ours and the ones Rocket catches.
+fn error_page(status: Status, message: &str) -> String {
+    html! {
+        (head(&status.to_string()))
+        body {
+            .error {
+                h1 { (status.code) " " (status.reason().unwrap_or_default()) }
+                p { (message) }
+                a href="/" { "Back to the index" }
+            }
+        }
+        (footer())
+    }
+    .into_string()
+}
+
+/// Renders the error page for requests that failed before reaching a handler,
+/// like unknown paths and malformed forms, instead of Rocket's default page.
+#[catch(default)]
+pub fn catcher(status: Status, _: &Request<'_>) -> (Status, (ContentType, String)) {
+    let message = match status.code {
+        404 => "There's nothing here",
+        413 => "That upload is too large",
+        400 | 422 => "That request couldn't be understood",
+        code if code >= 500 => "Something went wrong on our end",
+        _ => status.reason().unwrap_or_default(),
+    };
+    (status, (ContentType::HTML, error_page(status, message)))
+}
+
+/// Whole seconds to wait out `wait`, rounded up so that retrying right on time works.
+fn retry_after(wait: &Duration) -> u64 {
+    wait.as_secs() + u64::from(wait.subsec_nanos() > 0)
+}
+
+impl Error {
+    fn status(&self) -> Status {
+        match self {
             Error::Db(_) => Status::InternalServerError,
             Error::Image(_) => Status::InternalServerError,
             Error::Rocket(_) => Status::InternalServerError,
@@ -41,12 +145,151 @@ impl<'r, 'o: 'r> Responder<'r, 'o> for Error {
             Error::Banned(_) => Status::Ok,
             Error::MissingImage => Status::UnprocessableEntity,
             Error::MissingOrInvalidCaptchaID => Status::UnprocessableEntity,
+            Error::TooFast => Status::TooManyRequests,
+            Error::BlockedContent => Status::UnprocessableEntity,
+            Error::BoardLimitReached => Status::Forbidden,
+            Error::InvalidBoardName => Status::UnprocessableEntity,
+            Error::BoardExists => Status::Conflict,
+            Error::StorageQuotaExceeded => Status::InsufficientStorage,
+            Error::ReadOnly(_) => Status::ServiceUnavailable,
+            Error::InvisibleCharacters => Status::UnprocessableEntity,
+            Error::UnsupportedImageType => Status::UnsupportedMediaType,
+            Error::ThreadImageLimit => Status::UnprocessableEntity,
+            Error::MissingContent => Status::UnprocessableEntity,
+            Error::CaptchaLockout => Status::TooManyRequests,
+            Error::ThreadLocked => Status::UnprocessableEntity,
+            Error::ThreadArchived => Status::UnprocessableEntity,
+            Error::InvalidCss(_) => Status::UnprocessableEntity,
+            Error::AuthorTooLong => Status::UnprocessableEntity,
+            Error::ImagesDisabled => Status::UnprocessableEntity,
+            Error::ReportReasonTooLong => Status::UnprocessableEntity,
+            Error::Rejected => Status::UnprocessableEntity,
+            Error::InvalidCredentials => Status::Unauthorized,
+            Error::InvalidVideo => Status::UnprocessableEntity,
+            Error::UploadTooLarge => Status::PayloadTooLarge,
+            Error::DisallowedFileType => Status::UnsupportedMediaType,
+            Error::TooManyImages => Status::UnprocessableEntity,
+            Error::Flood(_) => Status::TooManyRequests,
+            Error::InvalidBan(_) => Status::UnprocessableEntity,
+            Error::HashCollision => Status::Conflict,
+            Error::ConfirmationMismatch => Status::UnprocessableEntity,
+            Error::TooManyFollowers => Status::ServiceUnavailable,
+            Error::TooLong(..) => Status::UnprocessableEntity,
+            Error::PasswordHash(_) => Status::InternalServerError,
+            Error::Storage(_) => Status::InternalServerError,
+        }
+    }
+
+    /// The status and error page to answer with.
+    /// Internal errors can carry SQL and paths, so unless `detailed` they stay in the logs
+    /// and the client gets an id to match its problem up with them.
+    fn page(&self, detailed: bool) -> (Status, String) {
+        let status = self.status();
+        let internal = matches!(
+            self,
+            Error::Db(_)
+                | Error::Image(_)
+                | Error::Rocket(_)
+                | Error::Dotenv(_)
+                | Error::Io(_)
+                | Error::PasswordHash(_)
+                | Error::Storage(_)
+        );
+        let message = if internal {
+            let request_id = uuid::Uuid::new_v4();
+            tracing::error!(error = %self, %request_id, "{status}");
+            if detailed {
+                self.to_string()
+            } else {
+                format!("Something went wrong on our end, request id {request_id}")
+            }
+        } else {
+            self.to_string()
+        };
+        (status, error_page(status, &message))
+    }
+}
+
+impl<'r, 'o: 'r> Responder<'r, 'o> for Error {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'o> {
+        let (status, f) = self.page(CONFIG.detailed_errors);
+        let retry = match &self {
+            Error::Flood(wait) => Some(retry_after(wait)),
+            _ => None,
         };
-        let f = format!("{self}");
-        Response::build()
+        let mut response = Response::build();
+        response
             .header(ContentType::HTML)
             .status(status)
-            .sized_body(f.len(), Cursor::new(f))
-            .ok()
+            .sized_body(f.len(), Cursor::new(f));
+        if let Some(wait) = retry {
+            response.raw_header("Retry-After", wait.to_string());
+        }
+        response.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db_error() -> Error {
+        Error::Db(sqlx::Error::Protocol(
+            "relation \"secret_table\" does not exist".to_string(),
+        ))
+    }
+
+    /// The id an internal error's page tells the client about.
+    fn request_id(page: &str) -> Option<uuid::Uuid> {
+        let (_, rest) = page.split_once("request id ")?;
+        rest.get(..36)?.parse().ok()
+    }
+
+    #[test]
+    fn internal_errors_hide_their_details() {
+        let (status, page) = db_error().page(false);
+        assert_eq!(status, Status::InternalServerError);
+        assert!(!page.contains("secret_table"));
+        assert!(request_id(&page).is_some());
+
+        let io = Error::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "/srv/ruburu/images/secret.png",
+        ));
+        let (status, page) = io.page(false);
+        assert_eq!(status, Status::InternalServerError);
+        assert!(!page.contains("/srv/ruburu"));
+        assert!(request_id(&page).is_some());
+    }
+
+    #[test]
+    fn every_internal_error_gets_its_own_id() {
+        let (_, first) = db_error().page(false);
+        let (_, second) = db_error().page(false);
+        assert_ne!(request_id(&first), request_id(&second));
+    }
+
+    #[test]
+    fn detailed_errors_show_the_details() {
+        let (_, page) = db_error().page(true);
+        assert!(page.contains("secret_table"));
+    }
+
+    #[test]
+    fn client_errors_keep_their_message() {
+        let (status, page) = Error::NotFound.page(false);
+        assert_eq!(status, Status::NotFound);
+        assert!(page.contains("There's nothing here"));
+        assert!(request_id(&page).is_none());
+    }
+
+    #[test]
+    fn error_pages_are_styled() {
+        for (_, page) in [Error::NotFound.page(false), db_error().page(false)] {
+            assert!(page.contains(r#"<link rel="stylesheet" href="/static/style.css">"#));
+            assert!(page.contains(r#"<a href="/">Back to the index</a>"#));
+        }
+        let page = error_page(Status::NotFound, "There's nothing here");
+        assert!(page.contains("<h1>404 Not Found</h1>"));
     }
 }
@@ -1,6 +1,11 @@
+use crate::{
+    config::{RateLimitBackend, StorageBackend, CONFIG},
+    models::{Board, Captcha, Post, Session},
+    rate_limit::{MemoryRateLimiter, PgRateLimiter, RateLimiter},
+};
 use rocket::{
     fairing::{self, Fairing, Info, Kind},
-    Build, Rocket,
+    Build, Orbit, Rocket,
 };
 use std::{env, future::Future, pin::Pin};
 
@@ -35,3 +40,175 @@ impl Fairing for DbManager {
         })
     }
 }
+
+/// Manages the `Box<dyn RateLimiter>` selected by `RATE_LIMIT_BACKEND`.
+/// Must be attached after `DbManager`.
+pub(crate) struct RateLimitManager;
+
+impl Fairing for RateLimitManager {
+    fn info(&self) -> Info {
+        Info {
+            name: "RateLimitManager",
+            kind: Kind::Singleton | Kind::Ignite,
+        }
+    }
+
+    fn on_ignite<'life0, 'async_trait>(
+        &'life0 self,
+        rocket: Rocket<Build>,
+    ) -> Pin<Box<dyn Future<Output = fairing::Result> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async {
+            let limiter: Box<dyn RateLimiter> = match CONFIG.rate_limit_backend {
+                RateLimitBackend::Memory => Box::new(MemoryRateLimiter::default()),
+                RateLimitBackend::Postgres => {
+                    let pool = rocket
+                        .state::<sqlx::PgPool>()
+                        .expect("DbManager must be attached before RateLimitManager")
+                        .clone();
+                    Box::new(PgRateLimiter::new(pool))
+                }
+            };
+            Ok(rocket.manage(limiter))
+        })
+    }
+}
+
+/// Creates `IMAGES_DIR` and `THUMBS_DIR` if they don't exist yet,
+/// so that the first upload on a fresh deployment doesn't fail.
+/// Does nothing unless files are stored locally.
+pub(crate) struct StorageDirs;
+
+impl Fairing for StorageDirs {
+    fn info(&self) -> Info {
+        Info {
+            name: "StorageDirs",
+            kind: Kind::Singleton | Kind::Ignite,
+        }
+    }
+
+    fn on_ignite<'life0, 'async_trait>(
+        &'life0 self,
+        rocket: Rocket<Build>,
+    ) -> Pin<Box<dyn Future<Output = fairing::Result> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async {
+            if CONFIG.storage_backend != StorageBackend::Local {
+                return Ok(rocket);
+            }
+            for dir in [&CONFIG.images_dir, &CONFIG.thumbs_dir] {
+                if let Err(e) = tokio::fs::create_dir_all(dir).await {
+                    tracing::error!("Couldn't create {}: {e}", dir.display());
+                    return Err(rocket);
+                }
+            }
+            Ok(rocket)
+        })
+    }
+}
+
+/// Periodically deletes boards that stayed empty for `EMPTY_BOARD_TTL_DAYS`.
+/// Does nothing unless that's set.
+pub(crate) struct BoardJanitor;
+
+impl Fairing for BoardJanitor {
+    fn info(&self) -> Info {
+        Info {
+            name: "BoardJanitor",
+            kind: Kind::Singleton | Kind::Liftoff,
+        }
+    }
+
+    fn on_liftoff<'life0, 'life1, 'async_trait>(
+        &'life0 self,
+        rocket: &'life1 Rocket<Orbit>,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async move {
+            let ttl_days = match CONFIG.empty_board_ttl_days {
+                Some(ttl_days) => ttl_days,
+                None => return,
+            };
+            let pool = rocket.state::<sqlx::PgPool>().unwrap().clone();
+            let shutdown = rocket.shutdown();
+            tokio::spawn(async move {
+                tokio::pin!(shutdown);
+                let mut interval = tokio::time::interval(CONFIG.board_cleanup_interval);
+                loop {
+                    tokio::select! {
+                        _ = interval.tick() => {}
+                        _ = &mut shutdown => break,
+                    }
+                    match Board::delete_stale_empty(ttl_days, &pool).await {
+                        Ok(names) => {
+                            for name in names {
+                                tracing::info!("Deleted empty board /{name}/");
+                            }
+                        }
+                        Err(e) => tracing::error!("Couldn't clean up empty boards: {e}"),
+                    }
+                }
+            });
+        })
+    }
+}
+
+/// Periodically deletes captchas older than `CAPTCHA_TTL_SECS` and sessions older than `SESSION_TTL_SECS`,
+/// and unsticks threads whose sticky ran out.
+pub(crate) struct Pruner;
+
+impl Fairing for Pruner {
+    fn info(&self) -> Info {
+        Info {
+            name: "Pruner",
+            kind: Kind::Singleton | Kind::Liftoff,
+        }
+    }
+
+    fn on_liftoff<'life0, 'life1, 'async_trait>(
+        &'life0 self,
+        rocket: &'life1 Rocket<Orbit>,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async move {
+            let pool = rocket.state::<sqlx::PgPool>().unwrap().clone();
+            let shutdown = rocket.shutdown();
+            tokio::spawn(async move {
+                tokio::pin!(shutdown);
+                let mut interval = tokio::time::interval(CONFIG.prune_interval);
+                loop {
+                    tokio::select! {
+                        _ = interval.tick() => {}
+                        _ = &mut shutdown => break,
+                    }
+                    match Captcha::prune(CONFIG.captcha_ttl, &pool).await {
+                        Ok(n) => tracing::info!("Pruned {n} expired captchas"),
+                        Err(e) => tracing::error!("Couldn't prune captchas: {e}"),
+                    }
+                    match Session::prune(CONFIG.session_ttl, &pool).await {
+                        Ok(n) => tracing::info!("Pruned {n} expired sessions"),
+                        Err(e) => tracing::error!("Couldn't prune sessions: {e}"),
+                    }
+                    match Post::clear_expired_stickies(&pool).await {
+                        Ok(n) => tracing::info!("Unstuck {n} threads whose sticky expired"),
+                        Err(e) => tracing::error!("Couldn't clear expired stickies: {e}"),
+                    }
+                }
+            });
+        })
+    }
+}
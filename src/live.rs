@@ -0,0 +1,40 @@
+use std::{collections::HashMap, sync::Mutex};
+use tokio::sync::broadcast;
+
+/// How many new posts a slow subscriber can fall behind by before it starts missing them.
+const BACKLOG: usize = 16;
+
+/// How many clients can follow one thread at a time.
+pub const MAX_SUBSCRIBERS: usize = 256;
+
+/// Posts made since a client started following a thread, rendered and ready to send.
+#[derive(Default)]
+pub struct LiveThreads {
+    channels: Mutex<HashMap<(String, i32), broadcast::Sender<String>>>,
+}
+
+impl LiveThreads {
+    /// Start following `thread` on `board`, unless it already has too many followers.
+    pub fn subscribe(&self, board: &str, thread: i32) -> Option<broadcast::Receiver<String>> {
+        let mut channels = self.channels.lock().unwrap();
+        let sender = channels
+            .entry((board.to_owned(), thread))
+            .or_insert_with(|| broadcast::channel(BACKLOG).0);
+        if sender.receiver_count() >= MAX_SUBSCRIBERS {
+            return None;
+        }
+        Some(sender.subscribe())
+    }
+
+    /// Hand a new post in `thread` to everyone following it.
+    /// A thread nobody follows anymore has its channel dropped.
+    pub fn publish(&self, board: &str, thread: i32, post: String) {
+        let mut channels = self.channels.lock().unwrap();
+        let key = (board.to_owned(), thread);
+        if let Some(sender) = channels.get(&key) {
+            if sender.send(post).is_err() {
+                channels.remove(&key);
+            }
+        }
+    }
+}
@@ -1,34 +1,78 @@
 #![feature(iter_intersperse)]
 
+mod config;
 mod errors;
 mod fairings;
+mod live;
 mod models;
+mod rate_limit;
 mod routes;
+mod storage;
 
-use crate::{errors::Error, routes::*};
-use rocket::{fs::FileServer, routes};
+use crate::{config::CONFIG, errors::Error, routes::*};
+use rocket::{catchers, fs::FileServer, routes};
 
 #[rocket::main]
 async fn main() -> Result<(), Error> {
     dotenv::dotenv()?;
     let _rocket = rocket::build()
         .attach(fairings::DbManager)
+        .attach(fairings::RateLimitManager)
+        .attach(fairings::StorageDirs)
+        .attach(fairings::BoardJanitor)
+        .attach(fairings::Pruner)
+        .manage(rate_limit::UploadSlots::default())
+        .manage(live::LiveThreads::default())
         .mount("/static", FileServer::from("./static"))
-        .mount("/thumbs", FileServer::from("./thumbs"))
-        .mount("/images", FileServer::from("./images"))
+        .mount("/thumbs", FileServer::from(&CONFIG.thumbs_dir))
+        .mount("/images", FileServer::from(&CONFIG.images_dir))
         .mount(
             "/",
             routes![
                 public::index,
                 public::board,
+                public::board_page,
                 public::thread,
+                public::catalog,
+                public::archive,
+                public::archived_thread,
+                public::live,
+                public::board_feed,
+                public::search,
+                public::permalink,
+                public::raw_post,
                 public::create_post,
+                public::create_post_json,
+                public::quick_reply,
+                public::check_captcha,
+                public::report,
                 admin::index,
                 admin::login_page,
                 admin::login,
-                admin::create_board
+                admin::create_board,
+                admin::edit_board_page,
+                admin::edit_board,
+                admin::delete_board_page,
+                admin::delete_board,
+                admin::rebuild_backlinks,
+                admin::board_css_page,
+                admin::set_board_css,
+                admin::block_image,
+                admin::delete_post,
+                admin::lock_thread,
+                admin::toggle_sticky,
+                admin::reports,
+                admin::dismiss_report,
+                admin::bans,
+                admin::ban,
+                admin::unban,
+                admin::stats,
+                api::boards,
+                api::threads,
+                api::thread
             ],
         )
+        .register("/", catchers![errors::catcher])
         .launch()
         .await?;
 
@@ -1,4 +1,9 @@
-use image::ImageEncoder;
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use hmac::{Hmac, Mac};
+use image::{AnimationDecoder, DynamicImage, GenericImageView, ImageEncoder, ImageFormat};
 use maud::{html, PreEscaped};
 use once_cell::sync::Lazy;
 use rand::prelude::StdRng;
@@ -7,58 +12,285 @@ use rocket::{
     async_trait,
     data::ToByteUnit,
     form::FromFormField,
-    http::Status,
+    http::{
+        impl_from_uri_param_identity,
+        uri::fmt::{Formatter, Query, UriDisplay},
+        ContentType, Status,
+    },
     request::{self, FromRequest},
+    serde::Serialize,
     uri, FromForm, Request,
 };
+use sha2::{Digest, Sha256};
 use sqlx::{
+    encode::IsNull,
+    error::BoxDynError,
+    postgres::{PgArgumentBuffer, PgTypeInfo, PgValueRef, Postgres},
     query, query_as,
     types::{ipnetwork::IpNetwork, time::PrimitiveDateTime, uuid::Uuid},
-    PgPool,
+    PgPool, Transaction, TypeInfo,
+};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    future::Future,
+    net::{IpAddr, Ipv6Addr},
+    ops::Deref,
+    path::Path,
+    time::{Duration, Instant},
 };
-use std::ops::Deref;
-use tokio::io::AsyncWriteExt;
+use tracing::Instrument;
 
-use crate::errors::Error;
+use crate::{
+    config::{InvisibleChars, ThumbFit, ThumbFormat, CONFIG},
+    errors::Error,
+    storage::{Folder, STORAGE},
+};
 
 static REPLY_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"&gt;&gt;(\d+)").unwrap());
+/// Spoilers can't contain greentext lines, so they never straddle a greentext block.
+static SPOILER_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\[spoiler\]((?:[^<]|<br>)+?)\[/spoiler\]").unwrap());
 static BOLD_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\*\*)(.+?)(\*\*)").unwrap());
 static ITALIC_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\*)(.+?)(\*)").unwrap());
+static BOARD_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"/([a-z0-9]{1,16})/").unwrap());
+static CSS_ESCAPE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\\([0-9a-fA-F]{1,6})\s?").unwrap());
+
+const REBUILD_BATCH: i64 = 500;
+const REPORT_REASON_MAX_CHARS: usize = 200;
+const SEARCH_RESULTS: i64 = 50;
+/// First path segments that routes other than the board pages use.
+const RESERVED_BOARD_NAMES: &[&str] = &[
+    "admin", "api", "captcha", "images", "report", "static", "submit", "thumbs",
+];
 
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
 pub struct Board {
     name: String,
     title: String,
+    repost_notice: bool,
+    sage_stops_bump: bool,
+    backlinks: bool,
+    max_thread_images: Option<i32>,
+    require_content_for_op: bool,
+    hide_email: bool,
+    autolock_after_days: Option<i32>,
+    captcha_scope: CaptchaScope,
+    custom_css: Option<String>,
+    text_only: bool,
+    subject_only_op: bool,
+    bump_limit: Option<i32>,
+    max_upload_bytes: Option<i64>,
+    allowed_mime_types: Option<Vec<String>>,
+    description: Option<String>,
+    description_html: Option<String>,
+    max_threads: Option<i32>,
 }
 
 impl Board {
     pub async fn get_all(pool: &PgPool) -> Result<Vec<Board>, sqlx::Error> {
-        query_as!(Board, "SELECT name, title FROM boards ORDER BY name")
-            .fetch_all(pool)
-            .await
+        query_as!(
+            Board,
+            r#"SELECT name, title, repost_notice, sage_stops_bump, backlinks, max_thread_images,
+                require_content_for_op, hide_email, autolock_after_days,
+                captcha_scope AS "captcha_scope: CaptchaScope", custom_css, text_only,
+                subject_only_op, bump_limit, max_upload_bytes, allowed_mime_types, description,
+                description_html, max_threads
+            FROM boards ORDER BY name"#
+        )
+        .fetch_all(pool)
+        .await
     }
 
     pub async fn get(name: &str, pool: &PgPool) -> Result<Option<Board>, sqlx::Error> {
         query_as!(
             Board,
-            "SELECT name, title FROM boards WHERE name = $1",
+            r#"SELECT name, title, repost_notice, sage_stops_bump, backlinks, max_thread_images,
+                require_content_for_op, hide_email, autolock_after_days,
+                captcha_scope AS "captcha_scope: CaptchaScope", custom_css, text_only,
+                subject_only_op, bump_limit, max_upload_bytes, allowed_mime_types, description,
+                description_html, max_threads
+            FROM boards WHERE name = $1"#,
             name
         )
         .fetch_optional(pool)
         .await
     }
 
-    pub async fn create(name: &str, title: &str, pool: &PgPool) -> Result<(), sqlx::Error> {
+    /// Create a board, returning its name as it was stored.
+    /// Names are lowercased, and must be 1 to 16 letters and digits that aren't taken by a route.
+    pub async fn create(
+        name: &str,
+        title: &str,
+        description: Option<&str>,
+        require_content_for_op: bool,
+        pool: &PgPool,
+    ) -> Result<String, Error> {
+        check_board_lengths(title, description)?;
+        let name = board_name(name)?;
+        if let Some(max_boards) = CONFIG.max_boards {
+            let count = query!(r#"SELECT COUNT(*) AS "count!" FROM boards"#)
+                .fetch_one(pool)
+                .await?
+                .count;
+            if count >= max_boards {
+                return Err(Error::BoardLimitReached);
+            }
+        }
+        let description_html = match description {
+            Some(description) => Some(render_description(description, pool).await?),
+            None => None,
+        };
+        let created = query!(
+            "INSERT INTO boards(name, title, require_content_for_op, description, description_html)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT DO NOTHING",
+            name,
+            title,
+            require_content_for_op,
+            description,
+            description_html
+        )
+        .execute(pool)
+        .await?
+        .rows_affected();
+        if created == 0 {
+            return Err(Error::BoardExists);
+        }
+        Ok(name)
+    }
+
+    /// Change the board's title, and its description or remove it with `None`.
+    pub async fn update(
+        name: &str,
+        title: &str,
+        description: Option<&str>,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        check_board_lengths(title, description)?;
+        let description_html = match description {
+            Some(description) => Some(render_description(description, pool).await?),
+            None => None,
+        };
+        let updated = query!(
+            "UPDATE boards SET title = $2, description = $3, description_html = $4
+            WHERE name = $1",
+            name,
+            title,
+            description,
+            description_html
+        )
+        .execute(pool)
+        .await?
+        .rows_affected();
+        if updated == 0 {
+            return Err(Error::NotFound);
+        }
+        Ok(())
+    }
+
+    /// Replace the board's stylesheet, or remove it with `None`.
+    pub async fn set_custom_css(name: &str, css: Option<&str>, pool: &PgPool) -> Result<(), Error> {
+        if let Some(css) = css {
+            check_css(css)?;
+        }
         query!(
-            "INSERT INTO boards(name, title)
-                VALUES ($1, $2)",
+            "UPDATE boards SET custom_css = $2 WHERE name = $1",
             name,
-            title
+            css
         )
         .execute(pool)
         .await?;
         Ok(())
     }
 
+    /// Delete board `name` along with all of its posts and everything attached to them.
+    /// Replies from other boards' posts into this one go too, but the posts themselves stay.
+    /// With `purge_images`, images no post refers to anymore are deleted as well, while
+    /// images still attached to posts on other boards are always kept.
+    pub async fn delete(name: &str, purge_images: bool, pool: &PgPool) -> Result<(), Error> {
+        let mut tx = pool.begin().await?;
+        query!(
+            "DELETE FROM replies WHERE message_board = $1 OR reply_board = $1",
+            name
+        )
+        .execute(&mut tx)
+        .await?;
+        query!("DELETE FROM reports WHERE board = $1", name)
+            .execute(&mut tx)
+            .await?;
+        let images: Vec<Uuid> = query!(
+            "DELETE FROM post_images WHERE board = $1 RETURNING image",
+            name
+        )
+        .fetch_all(&mut tx)
+        .await?
+        .into_iter()
+        .map(|r| r.image)
+        .collect();
+        query!("DELETE FROM posts WHERE board = $1", name)
+            .execute(&mut tx)
+            .await?;
+        let deleted = query!("DELETE FROM boards WHERE name = $1", name)
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+        if deleted == 0 {
+            return Err(Error::NotFound);
+        }
+        let orphans = if purge_images {
+            query!(
+                "DELETE FROM images
+                WHERE hash = ANY($1)
+                    AND NOT EXISTS (SELECT 1 FROM post_images WHERE image = images.hash)
+                    AND NOT EXISTS (SELECT 1 FROM posts WHERE image = images.hash)
+                RETURNING hash, thumb_ext",
+                &images
+            )
+            .fetch_all(&mut tx)
+            .await?
+        } else {
+            Vec::new()
+        };
+        tx.commit().await?;
+
+        // Files can't be rolled back with the rows, so they only go once the rows are gone
+        // for good. One that can't be deleted is just left behind.
+        for image in orphans {
+            let thumb = format!("{}.{}", image.hash, image.thumb_ext);
+            for (folder, file) in [
+                (Folder::Images, image.hash.to_string()),
+                (Folder::Thumbs, thumb),
+            ] {
+                if let Err(e) = STORAGE.delete(folder, &file).await {
+                    tracing::warn!("Couldn't delete {file} from {folder:?}: {e}");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete boards that never got a post within `ttl_days` of being created,
+    /// returning their names.
+    pub async fn delete_stale_empty(
+        ttl_days: i32,
+        pool: &PgPool,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        Ok(query!(
+            "DELETE FROM boards
+            WHERE created_at < NOW() - make_interval(days => $1)
+                AND NOT EXISTS (SELECT 1 FROM posts WHERE posts.board = boards.name)
+            RETURNING name",
+            ttl_days
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|r| r.name)
+        .collect())
+    }
+
     /// Get a reference to the board's name.
     #[must_use]
     pub fn name(&self) -> &str {
@@ -70,6 +302,147 @@ impl Board {
     pub fn title(&self) -> &str {
         self.title.as_ref()
     }
+
+    /// Whether posts should point out earlier uses of their image.
+    #[must_use]
+    pub fn repost_notice(&self) -> bool {
+        self.repost_notice
+    }
+
+    /// Whether saged replies keep a thread from bumping.
+    /// When off, sage is purely cosmetic and only the bump limit stops a thread from rising.
+    #[must_use]
+    pub fn sage_stops_bump(&self) -> bool {
+        self.sage_stops_bump
+    }
+
+    /// The board's current thread ordering rules.
+    #[must_use]
+    pub fn thread_order(&self) -> ThreadOrder {
+        ThreadOrder {
+            sage_stops_bump: self.sage_stops_bump,
+            bump_limit: self.bump_limit,
+        }
+    }
+
+    /// Whether `>>` references are recorded and shown as backlinks on the quoted post.
+    #[must_use]
+    pub fn backlinks(&self) -> bool {
+        self.backlinks
+    }
+
+    /// How many images a thread may hold, OP included.
+    #[must_use]
+    pub fn max_thread_images(&self) -> Option<i32> {
+        self.max_thread_images
+    }
+
+    /// Whether new threads need some text in addition to the image.
+    #[must_use]
+    pub fn require_content_for_op(&self) -> bool {
+        self.require_content_for_op
+    }
+
+    /// Whether the email field is left out of the post form, and ignored if submitted anyway.
+    #[must_use]
+    pub fn hide_email(&self) -> bool {
+        self.hide_email
+    }
+
+    /// Threads without a post for this many days get locked.
+    #[must_use]
+    pub fn autolock_after_days(&self) -> Option<i32> {
+        self.autolock_after_days
+    }
+
+    /// Extra CSS put into a `<style>` block on the board's pages.
+    #[must_use]
+    pub fn custom_css(&self) -> Option<&str> {
+        self.custom_css.as_deref()
+    }
+
+    /// Which posts on this board need a solved captcha.
+    #[must_use]
+    pub fn captcha_scope(&self) -> CaptchaScope {
+        self.captcha_scope
+    }
+
+    /// Whether a post to `thread`, or a new thread if `None`, needs a solved captcha.
+    #[must_use]
+    pub fn captcha_required(&self, thread: Option<i32>) -> bool {
+        match self.captcha_scope {
+            CaptchaScope::None => false,
+            CaptchaScope::Threads => thread.is_none(),
+            CaptchaScope::All => true,
+        }
+    }
+
+    /// Whether the board takes text posts only.
+    #[must_use]
+    pub fn text_only(&self) -> bool {
+        self.text_only
+    }
+
+    /// Whether a title is enough to satisfy `require_content_for_op`.
+    #[must_use]
+    pub fn subject_only_op(&self) -> bool {
+        self.subject_only_op
+    }
+
+    /// Posts in a thread past which replies are saged automatically.
+    #[must_use]
+    pub fn bump_limit(&self) -> Option<i32> {
+        self.bump_limit
+    }
+
+    /// Largest upload the board takes, in bytes, on top of the site-wide `MAX_UPLOAD_BYTES`.
+    #[must_use]
+    pub fn max_upload_bytes(&self) -> Option<i64> {
+        self.max_upload_bytes
+    }
+
+    /// MIME types the board takes uploads of, out of the ones allowed site-wide.
+    #[must_use]
+    pub fn allowed_mime_types(&self) -> Option<&[String]> {
+        self.allowed_mime_types.as_deref()
+    }
+
+    /// Get the board's description as written, shown above the post form.
+    #[must_use]
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Get the board's rendered description.
+    #[must_use]
+    pub fn description_html(&self) -> Option<&str> {
+        self.description_html.as_deref()
+    }
+
+    /// Get the most threads the board keeps, past which the least recently bumped are pruned.
+    #[must_use]
+    pub fn max_threads(&self) -> Option<i32> {
+        self.max_threads
+    }
+}
+
+#[derive(sqlx::Type, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "captcha_scope")]
+#[sqlx(rename_all = "lowercase")]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum CaptchaScope {
+    None,
+    Threads,
+    All,
+}
+
+/// The rules that decide where a thread sits on its board.
+#[derive(Debug, Clone)]
+pub struct ThreadOrder {
+    /// See [`Board::sage_stops_bump`].
+    pub sage_stops_bump: bool,
+    /// Replies past this many posts in a thread don't bump it.
+    pub bump_limit: Option<i32>,
 }
 
 pub struct Post {
@@ -85,17 +458,74 @@ pub struct Post {
     thread: i32,
     ip: IpNetwork,
     image: Option<Uuid>,
+    is_sticky: bool,
+    sticky_until: Option<PrimitiveDateTime>,
+    locked: bool,
+    deleted_at: Option<PrimitiveDateTime>,
+    tripcode: Option<String>,
+    archived: bool,
+    poster: Option<Vec<u8>>,
 }
 
 impl Post {
-    pub async fn for_thread(board: &str, id: i32, pool: &PgPool) -> Result<Vec<Post>, Error> {
-        let res = query_as!(
+    pub async fn get(board: &str, id: i32, pool: &PgPool) -> Result<Option<Post>, sqlx::Error> {
+        query_as!(
             Post,
-            "SELECT * FROM posts WHERE thread = $1 AND board = $2",
+            "SELECT * FROM posts WHERE id = $1 AND board = $2 AND deleted_at IS NULL",
             id,
             board
         )
-        .fetch_all(pool)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Unstick threads whose `sticky_until` has passed, returning how many there were.
+    /// Board listings already ignore expired stickies, this just tidies up after them.
+    pub async fn clear_expired_stickies(pool: &PgPool) -> Result<u64, sqlx::Error> {
+        Ok(query!(
+            "UPDATE posts
+            SET is_sticky = FALSE, sticky_until = NULL
+            WHERE is_sticky AND sticky_until <= NOW()"
+        )
+        .execute(pool)
+        .await?
+        .rows_affected())
+    }
+
+    /// When `ip` last posted anywhere, or last started a thread if `threads_only` is set.
+    /// Deleted posts still count, so deleting a post doesn't skip the cooldown.
+    pub async fn last_post_at_for_ip(
+        ip: IpNetwork,
+        threads_only: bool,
+        pool: &PgPool,
+    ) -> Result<Option<PrimitiveDateTime>, sqlx::Error> {
+        Ok(query!(
+            "SELECT MAX(posted_at) AS last FROM posts WHERE ip = $1 AND (id = thread OR NOT $2)",
+            ip,
+            threads_only
+        )
+        .fetch_one(pool)
+        .await?
+        .last)
+    }
+
+    pub async fn for_thread(board: &str, id: i32, pool: &PgPool) -> Result<Vec<Post>, Error> {
+        let res = timed(
+            "for_thread",
+            query_as!(
+                Post,
+                "SELECT * FROM posts
+                WHERE thread = $1 AND board = $2 AND deleted_at IS NULL
+                    AND EXISTS (
+                        SELECT 1 FROM posts op
+                        WHERE op.board = $2 AND op.id = $1 AND op.deleted_at IS NULL
+                    )
+                ORDER BY id",
+                id,
+                board
+            )
+            .fetch_all(pool),
+        )
         .await?;
         if res.is_empty() {
             Err(Error::NotFound)
@@ -104,80 +534,352 @@ impl Post {
         }
     }
 
-    pub async fn threads_for_board(board: &str, pool: &PgPool) -> Result<Vec<Post>, sqlx::Error> {
-        query_as!(
-            Post,
-            "WITH threads AS (
-                SELECT DISTINCT ON (posts.thread) posts.thread as id, max(posts.posted_at) as last_post
+    /// Get the OPs of threads on `board`, in the order given by `order`.
+    /// With `per_page` set only that many threads are returned, starting at the 0-based `page`.
+    pub async fn threads_for_board(
+        board: &Board,
+        order: &ThreadOrder,
+        page: i64,
+        per_page: Option<i64>,
+        pool: &PgPool,
+    ) -> Result<Vec<Post>, sqlx::Error> {
+        timed(
+            "threads_for_board",
+            query_as!(
+                Post,
+                "SELECT posts.*
                 FROM posts
-                WHERE posts.board = $1 AND (posts.thread = posts.id OR NOT posts.sage)
-                GROUP BY posts.thread
+                    JOIN board_threads($1, $2, $3) AS threads ON posts.id = threads.id
+                WHERE posts.board = $1
+                ORDER BY threads.position
+                LIMIT $4 OFFSET $5",
+                board.name(),
+                order.sage_stops_bump,
+                order.bump_limit,
+                per_page,
+                page * per_page.unwrap_or(0)
+            )
+            .fetch_all(pool),
+        )
+        .await
+    }
+
+    /// Get the newest `n` replies in each of the threads `ids` on `board` in one query,
+    /// oldest first and keyed by thread id, for showing under the OPs on the board index.
+    /// Threads without replies are left out of the map.
+    pub async fn last_replies(
+        ids: &[i32],
+        board: &str,
+        n: i64,
+        pool: &PgPool,
+    ) -> Result<HashMap<i32, Vec<Post>>, sqlx::Error> {
+        let rows = query_as!(
+            Post,
+            "SELECT * FROM posts
+            WHERE board = $1 AND id IN (
+                SELECT id FROM (
+                    SELECT id, ROW_NUMBER() OVER (
+                        PARTITION BY thread ORDER BY posted_at DESC, id DESC
+                    ) AS rank
+                    FROM posts
+                    WHERE board = $1 AND thread = ANY($2) AND id <> thread AND deleted_at IS NULL
+                ) tails
+                WHERE rank <= $3
             )
-            SELECT posts.*
+            ORDER BY posted_at, id",
+            board,
+            ids,
+            n
+        )
+        .fetch_all(pool)
+        .await?;
+        let mut tails: HashMap<i32, Vec<Post>> = HashMap::new();
+        for post in rows {
+            tails.entry(post.thread).or_default().push(post);
+        }
+        Ok(tails)
+    }
+
+    /// Count the posts in each of the threads `ids` on `board`, OPs included, keyed by thread id.
+    pub async fn thread_sizes(
+        ids: &[i32],
+        board: &str,
+        pool: &PgPool,
+    ) -> Result<HashMap<i32, i64>, sqlx::Error> {
+        Ok(query!(
+            r#"SELECT thread, COUNT(*) AS "count!"
+            FROM posts
+            WHERE board = $1 AND thread = ANY($2) AND deleted_at IS NULL
+            GROUP BY thread"#,
+            board,
+            ids
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|r| (r.thread, r.count))
+        .collect())
+    }
+
+    /// Get the newest `limit` posts on `board` across all threads, newest first.
+    pub async fn recent_for_board(
+        board: &str,
+        limit: i64,
+        pool: &PgPool,
+    ) -> Result<Vec<Post>, sqlx::Error> {
+        query_as!(
+            Post,
+            "SELECT * FROM posts
+            WHERE board = $1 AND deleted_at IS NULL
+            ORDER BY posted_at DESC, id DESC
+            LIMIT $2",
+            board,
+            limit
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Find posts on `board` whose text contains every word of `query`, best matches first.
+    /// Words are matched by prefix, and anything that isn't a letter or digit is ignored,
+    /// so no input can make for an invalid `tsquery`.
+    pub async fn search(board: &str, query: &str, pool: &PgPool) -> Result<Vec<Post>, sqlx::Error> {
+        let terms = search_terms(query);
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+        query_as!(
+            Post,
+            "SELECT * FROM posts
+            WHERE board = $1 AND deleted_at IS NULL
+                AND to_tsvector('simple', COALESCE(plaintext_content, '')) @@ to_tsquery('simple', $2)
+            ORDER BY ts_rank(to_tsvector('simple', COALESCE(plaintext_content, '')), to_tsquery('simple', $2)) DESC,
+                posted_at DESC
+            LIMIT $3",
+            board,
+            terms,
+            SEARCH_RESULTS
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Count the threads on `board`.
+    pub async fn thread_count(board: &str, pool: &PgPool) -> Result<i64, sqlx::Error> {
+        Ok(query!(
+            r#"SELECT COUNT(*) AS "count!"
             FROM posts
-                LEFT JOIN threads ON posts.thread = threads.id
-            WHERE posts.id = threads.id
-            ORDER BY threads.last_post DESC",
+            WHERE board = $1 AND id = thread AND deleted_at IS NULL AND NOT archived"#,
+            board
+        )
+        .fetch_one(pool)
+        .await?
+        .count)
+    }
+
+    /// Get the OPs of `board`'s archived threads, most recently started first.
+    pub async fn archived_threads(board: &str, pool: &PgPool) -> Result<Vec<Post>, sqlx::Error> {
+        query_as!(
+            Post,
+            "SELECT * FROM posts
+            WHERE board = $1 AND id = thread AND archived AND deleted_at IS NULL
+            ORDER BY posted_at DESC",
             board
         )
         .fetch_all(pool)
         .await
     }
 
+    /// Get every post of archived `thread`, failing with `Error::NotFound` if it isn't archived.
+    pub async fn archived_thread(
+        board: &str,
+        thread: i32,
+        pool: &PgPool,
+    ) -> Result<Vec<Post>, Error> {
+        let posts = Post::for_thread(board, thread, pool).await?;
+        if posts.iter().any(|p| p.id == thread && p.archived) {
+            Ok(posts)
+        } else {
+            Err(Error::NotFound)
+        }
+    }
+
+    /// Get a page of the OPs of threads on `board` whose subject or text contains `q`, ignoring case,
+    /// along with how many replies and reply images each thread has, in `sort` order.
+    /// Ties, and the `Bump` sort itself, follow the board's usual order.
+    pub async fn catalog_for_board(
+        board: &Board,
+        q: Option<&str>,
+        sort: CatalogSort,
+        page: i64,
+        per_page: i64,
+        pool: &PgPool,
+    ) -> Result<Vec<CatalogEntry>, sqlx::Error> {
+        let order = board.thread_order();
+        let ops = query_as!(
+            Post,
+            "SELECT posts.*
+            FROM posts
+                JOIN board_threads($1, $2, $3) AS threads ON posts.id = threads.id
+            WHERE posts.board = $1
+                AND ($4::TEXT IS NULL OR posts.title ILIKE $4 OR posts.plaintext_content ILIKE $4)
+            ORDER BY
+                CASE WHEN $5 = 'created' THEN posts.posted_at END DESC,
+                CASE WHEN $5 = 'replies' THEN (
+                    SELECT COUNT(*) FROM posts replies
+                    WHERE replies.board = $1 AND replies.thread = posts.id
+                        AND replies.id <> replies.thread AND replies.deleted_at IS NULL
+                ) END DESC,
+                threads.position
+            LIMIT $6 OFFSET $7",
+            board.name(),
+            order.sage_stops_bump,
+            order.bump_limit,
+            q.map(like_pattern),
+            sort.as_str(),
+            per_page,
+            page * per_page
+        )
+        .fetch_all(pool)
+        .await?;
+        let ids: Vec<i32> = ops.iter().map(Post::id).collect();
+        let mut counts: HashMap<i32, (i64, i64)> = query!(
+            r#"SELECT thread, COUNT(DISTINCT id) AS "replies!", COUNT(post_images.image) AS "images!"
+            FROM posts
+                LEFT JOIN post_images
+                    ON (post_images.post_id, post_images.board) = (posts.id, posts.board)
+            WHERE posts.board = $1 AND thread = ANY($2) AND id <> thread AND deleted_at IS NULL
+            GROUP BY thread"#,
+            board.name(),
+            &ids
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|c| (c.thread, (c.replies, c.images)))
+        .collect();
+        Ok(ops
+            .into_iter()
+            .map(|op| {
+                let (replies, images) = counts.remove(&op.id).unwrap_or_default();
+                CatalogEntry {
+                    replies,
+                    images,
+                    op,
+                }
+            })
+            .collect())
+    }
+
+    /// How many threads [`Post::catalog_for_board`] has to show for `q` in total.
+    pub async fn catalog_count(
+        board: &Board,
+        q: Option<&str>,
+        pool: &PgPool,
+    ) -> Result<i64, sqlx::Error> {
+        let order = board.thread_order();
+        Ok(query!(
+            r#"SELECT COUNT(*) AS "count!"
+            FROM posts
+                JOIN board_threads($1, $2, $3) AS threads ON posts.id = threads.id
+            WHERE posts.board = $1
+                AND ($4::TEXT IS NULL OR posts.title ILIKE $4 OR posts.plaintext_content ILIKE $4)"#,
+            board.name(),
+            order.sage_stops_bump,
+            order.bump_limit,
+            q.map(like_pattern)
+        )
+        .fetch_one(pool)
+        .await?
+        .count)
+    }
+
+    /// Create a new thread and return its id.
+    ///
+    /// Ids are handed out by `boards.next_post_id` inside the same transaction as the insert,
+    /// so the board row stays locked until the post is committed. Concurrent posts on one board are
+    /// serialized by that lock and always get distinct ids, and since a failed post rolls the counter
+    /// back with it, ids stay contiguous.
     #[allow(clippy::too_many_arguments)]
     pub async fn create_thread(
-        board: &str,
+        board: &Board,
         title: Option<&str>,
         author: Option<&str>,
         email: Option<&str>,
         sage: bool,
         content: Option<&str>,
         ip: IpNetwork,
-        image: Image,
+        poster: &[u8],
+        images: Vec<Image>,
         pool: &PgPool,
-    ) -> Result<i32, sqlx::Error> {
+    ) -> Result<i32, Error> {
+        check_post_lengths(title, email, content)?;
+        let (author, tripcode) = parse_author(author)?;
+        if images.len() > CONFIG.max_images_per_post {
+            return Err(Error::TooManyImages);
+        }
+        if images.is_empty() && !board.text_only() {
+            return Err(Error::MissingImage);
+        }
+        // Text boards have nothing else to start a thread with.
+        let has_text = content.is_some() || (board.subject_only_op() && title.is_some());
+        if (board.require_content_for_op() || board.text_only()) && !has_text {
+            return Err(Error::MissingContent);
+        }
         let mut tx = pool.begin().await?;
         let per_board_id = query!(
             "UPDATE boards
             SET next_post_id = next_post_id + 1
             WHERE name = $1
             RETURNING next_post_id;",
-            board
+            board.name()
         )
         .fetch_one(&mut tx)
         .await?
         .next_post_id;
 
-        let (html_content, replied) = Post::html_body(content, board, pool).await?;
+        let (html_content, replied) = Post::html_body(content, board.name(), pool).await?;
 
         query!(
-            "INSERT INTO posts(id, board, title, author, email, sage, plaintext_content, html_content, thread, ip, image)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $1, $9, $10)
+            "INSERT INTO posts(id, board, title, author, tripcode, email, sage, plaintext_content, html_content, thread, ip, image, poster)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $1, $10, $11, $12)
             RETURNING id;",
             per_board_id,
-            board,
+            board.name(),
             title,
             author,
+            tripcode,
             email,
             sage,
             content,
             html_content,
             ip,
-            image.hash()
+            images.first().map(Image::hash),
+            poster
         )
         .fetch_one(&mut tx)
         .await?;
+        attach_images(&mut tx, board.name(), per_board_id, &images).await?;
 
-        for message in replied {
-            query!(
-                "INSERT INTO replies(message_id, message_board, reply_id, reply_board, reply_thread)
-                VALUES ($1, $2, $3, $2, $3);",
-                message,
-                board,
-                per_board_id
-            )
-            .execute(pool)
-            .await?;
+        if board.backlinks() {
+            for message in replied {
+                query!(
+                    "INSERT INTO replies(message_id, message_board, reply_id, reply_board, reply_thread)
+                    VALUES ($1, $2, $3, $2, $3);",
+                    message,
+                    board.name(),
+                    per_board_id
+                )
+                .execute(&mut tx)
+                .await?;
+            }
+        }
+
+        if let Some(max_threads) = board.max_threads() {
+            let archived = Post::archive_threads(&mut tx, board, max_threads).await?;
+            if !archived.is_empty() {
+                tracing::info!(board = board.name(), ?archived, "Archived threads");
+            }
         }
 
         tx.commit().await?;
@@ -185,9 +887,37 @@ impl Post {
         Ok(per_board_id)
     }
 
+    /// Move the threads that sit past the first `max_threads` of `board` in its usual order
+    /// into the archive, returning their ids. Archived threads keep their posts and backlinks,
+    /// but drop off the board and take no more replies.
+    async fn archive_threads(
+        tx: &mut Transaction<'_, Postgres>,
+        board: &Board,
+        max_threads: i32,
+    ) -> Result<Vec<i32>, sqlx::Error> {
+        let order = board.thread_order();
+        Ok(query!(
+            "UPDATE posts SET archived = TRUE, is_sticky = FALSE, sticky_until = NULL
+            WHERE board = $1
+                AND id IN (SELECT id FROM board_threads($1, $2, $3) WHERE position > $4)
+            RETURNING id",
+            board.name(),
+            order.sage_stops_bump,
+            order.bump_limit,
+            i64::from(max_threads)
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|p| p.id)
+        .collect())
+    }
+
+    /// Reply to `thread` and return the new post's id.
+    /// Ids are allocated the same way as in [`Post::create_thread`].
     #[allow(clippy::too_many_arguments)]
     pub async fn create(
-        board: &str,
+        board: &Board,
         thread: i32,
         title: Option<&str>,
         author: Option<&str>,
@@ -195,57 +925,250 @@ impl Post {
         sage: bool,
         content: Option<&str>,
         ip: IpNetwork,
-        image: Option<Image>,
+        poster: &[u8],
+        images: Vec<Image>,
         pool: &PgPool,
-    ) -> Result<i32, sqlx::Error> {
+    ) -> Result<i32, Error> {
+        check_post_lengths(title, email, content)?;
+        let (author, tripcode) = parse_author(author)?;
+        if images.len() > CONFIG.max_images_per_post {
+            return Err(Error::TooManyImages);
+        }
         let mut tx = pool.begin().await?;
+        if let Some(days) = board.autolock_after_days() {
+            query!(
+                "UPDATE posts
+                SET locked = TRUE
+                WHERE board = $1 AND id = $2 AND NOT locked
+                    AND (
+                        SELECT MAX(p.posted_at) FROM posts p WHERE p.board = $1 AND p.thread = $2
+                    ) < NOW() - make_interval(days => $3)",
+                board.name(),
+                thread,
+                days
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+        // `thread` comes straight from the post form, so make sure it names an OP on this board.
+        let op = query!(
+            "SELECT locked, archived FROM posts
+            WHERE board = $1 AND id = $2 AND thread = id AND deleted_at IS NULL",
+            board.name(),
+            thread
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(Error::NotFound)?;
+        if op.archived {
+            return Err(Error::ThreadArchived);
+        }
+        if op.locked {
+            return Err(Error::ThreadLocked);
+        }
+        if let (false, Some(max)) = (images.is_empty(), board.max_thread_images()) {
+            let count = query!(
+                r#"SELECT COUNT(*) AS "count!"
+                FROM post_images
+                    JOIN posts ON (posts.id, posts.board) = (post_images.post_id, post_images.board)
+                WHERE posts.board = $1 AND posts.thread = $2"#,
+                board.name(),
+                thread
+            )
+            .fetch_one(&mut tx)
+            .await?
+            .count;
+            if count + images.len() as i64 > max.into() {
+                return Err(Error::ThreadImageLimit);
+            }
+        }
+        let mut sage = sage;
+        if let Some(limit) = board.bump_limit() {
+            let count = query!(
+                r#"SELECT COUNT(*) AS "count!"
+                FROM posts
+                WHERE board = $1 AND thread = $2 AND deleted_at IS NULL"#,
+                board.name(),
+                thread
+            )
+            .fetch_one(&mut tx)
+            .await?
+            .count;
+            if count >= limit.into() {
+                sage = true;
+            }
+        }
         let per_board_id = query!(
             "UPDATE boards
             SET next_post_id = next_post_id + 1
             WHERE name = $1
             RETURNING next_post_id;",
-            board
+            board.name()
         )
         .fetch_one(&mut tx)
         .await?
         .next_post_id;
 
-        let (html_content, replied) = Post::html_body(content, board, pool).await?;
+        let (html_content, replied) = Post::html_body(content, board.name(), pool).await?;
 
         query!(
-            "INSERT INTO posts(id, board, title, author, email, sage, plaintext_content, html_content, thread, ip, image)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9,  $10, $11);",
+            "INSERT INTO posts(id, board, title, author, tripcode, email, sage, plaintext_content, html_content, thread, ip, image, poster)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10,  $11, $12, $13);",
             per_board_id,
-            board,
+            board.name(),
             title,
             author,
+            tripcode,
             email,
             sage,
             content,
             html_content,
             thread,
             ip,
-            image.map(|i|i.hash())
+            images.first().map(Image::hash),
+            poster
         )
-        .execute(pool)
+        .execute(&mut tx)
         .await?;
+        attach_images(&mut tx, board.name(), per_board_id, &images).await?;
 
-        for message in replied {
-            query!(
-                "INSERT INTO replies(message_id, message_board, reply_id, reply_board, reply_thread)
-                VALUES ($1, $2, $3, $2, $4);",
-                message,
-                board,
-                per_board_id,
-                thread
-            )
-            .execute(pool)
-            .await?;
+        if board.backlinks() {
+            for message in replied {
+                query!(
+                    "INSERT INTO replies(message_id, message_board, reply_id, reply_board, reply_thread)
+                    VALUES ($1, $2, $3, $2, $4);",
+                    message,
+                    board.name(),
+                    per_board_id,
+                    thread
+                )
+                .execute(&mut tx)
+                .await?;
+            }
         }
         tx.commit().await?;
         Ok(per_board_id)
     }
 
+    /// Hide a post, or a whole thread if it's the OP, while keeping the row around.
+    /// Returns the post's thread.
+    pub async fn delete(board: &str, id: i32, pool: &PgPool) -> Result<i32, Error> {
+        let mut tx = pool.begin().await?;
+        let post = query!(
+            "UPDATE posts SET deleted_at = NOW()
+            WHERE board = $1 AND id = $2 AND deleted_at IS NULL
+            RETURNING thread",
+            board,
+            id
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(Error::NotFound)?;
+        // Drop the backlinks the post made, so the posts it quoted don't link to nothing.
+        // Deleting an OP hides its whole thread, which takes the thread's backlinks with it.
+        query!(
+            "DELETE FROM replies
+            WHERE reply_board = $1 AND (reply_id = $2 OR ($2 = $3 AND reply_thread = $2))",
+            board,
+            id,
+            post.thread
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(post.thread)
+    }
+
+    /// Pin a thread to the top of its board, until `until` if given, or unpin it.
+    pub async fn set_sticky(
+        board: &str,
+        thread: i32,
+        sticky: bool,
+        until: Option<PrimitiveDateTime>,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        let res = query!(
+            "UPDATE posts
+            SET is_sticky = $3, sticky_until = $4
+            WHERE board = $1 AND id = $2 AND thread = id",
+            board,
+            thread,
+            sticky,
+            until
+        )
+        .execute(pool)
+        .await?;
+        if res.rows_affected() == 0 {
+            Err(Error::NotFound)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Close a thread to new replies, or open it back up.
+    pub async fn set_locked(
+        board: &str,
+        thread: i32,
+        locked: bool,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        let res = query!(
+            "UPDATE posts
+            SET locked = $3
+            WHERE board = $1 AND id = $2 AND thread = id",
+            board,
+            thread,
+            locked
+        )
+        .execute(pool)
+        .await?;
+        if res.rows_affected() == 0 {
+            Err(Error::NotFound)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Get every image attached to the post, in the order they were uploaded.
+    pub async fn images(&self, pool: &PgPool) -> Result<Vec<Uuid>, sqlx::Error> {
+        Ok(query!(
+            "SELECT image FROM post_images
+            WHERE board = $1 AND post_id = $2
+            ORDER BY position",
+            self.board,
+            self.id
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|r| r.image)
+        .collect())
+    }
+
+    /// Get the images attached to each of the posts `ids` on `board` in one query,
+    /// keyed by post id. Posts without images are left out of the map.
+    pub async fn images_for_posts(
+        ids: &[i32],
+        board: &str,
+        pool: &PgPool,
+    ) -> Result<HashMap<i32, Vec<Uuid>>, sqlx::Error> {
+        let rows = query!(
+            "SELECT post_id, image
+            FROM post_images
+            WHERE post_id = ANY($1) AND board = $2
+            ORDER BY position",
+            ids,
+            board
+        )
+        .fetch_all(pool)
+        .await?;
+        let mut images: HashMap<i32, Vec<Uuid>> = HashMap::new();
+        for row in rows {
+            images.entry(row.post_id).or_default().push(row.image);
+        }
+        Ok(images)
+    }
+
     /// Get the post's replies.
     pub async fn replies(&self, pool: &PgPool) -> Result<Vec<Reply>, sqlx::Error> {
         query_as!(
@@ -260,64 +1183,127 @@ impl Post {
         .await
     }
 
-    async fn html_body(
-        body: Option<&str>,
+    /// Get the replies to each of the posts `ids` on `board` in one query, keyed by post id.
+    /// Posts without replies are left out of the map.
+    pub async fn replies_for_posts(
+        ids: &[i32],
         board: &str,
         pool: &PgPool,
-    ) -> Result<(String, Vec<i32>), sqlx::Error> {
-        if let Some(body) = body {
-            let body = html! {
-                @for line in body.lines() {
-                    @if line.starts_with('>') && line.chars().nth(1) != Some('>') {
-                        .green-text { (line) }
-                    } @else { (line) }
-                    br;
+    ) -> Result<HashMap<i32, Vec<Reply>>, sqlx::Error> {
+        let rows = query!(
+            "SELECT message_id, reply_id, reply_board, reply_thread, posts.poster
+            FROM replies
+                JOIN posts ON (posts.id, posts.board) = (replies.reply_id, replies.reply_board)
+            WHERE message_id = ANY($1) AND message_board = $2",
+            ids,
+            board
+        )
+        .fetch_all(pool)
+        .await?;
+        let mut replies: HashMap<i32, Vec<Reply>> = HashMap::new();
+        for row in rows {
+            replies.entry(row.message_id).or_default().push(Reply {
+                reply_id: row.reply_id,
+                reply_board: row.reply_board,
+                reply_thread: row.reply_thread,
+                poster: row.poster,
+            });
+        }
+        Ok(replies)
+    }
+
+    /// Recompute the `replies` rows of every post on `board` from its content.
+    /// Posts are re-parsed in batches of `REBUILD_BATCH`, all inside one transaction.
+    /// A board with backlinks turned off just has its rows cleared.
+    pub async fn rebuild_replies(board: &Board, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        query!("DELETE FROM replies WHERE reply_board = $1", board.name())
+            .execute(&mut tx)
+            .await?;
+        if !board.backlinks() {
+            tx.commit().await?;
+            return Ok(());
+        }
+        let board = board.name();
+
+        let mut last_id = i32::MIN;
+        loop {
+            let batch = query!(
+                "SELECT id, thread, plaintext_content
+                FROM posts
+                WHERE board = $1 AND id > $2 AND deleted_at IS NULL
+                ORDER BY id
+                LIMIT $3",
+                board,
+                last_id,
+                REBUILD_BATCH
+            )
+            .fetch_all(&mut tx)
+            .await?;
+            if batch.is_empty() {
+                break;
+            }
+            last_id = batch.last().unwrap().id;
+
+            let mut message_ids = Vec::new();
+            let mut reply_ids = Vec::new();
+            let mut reply_threads = Vec::new();
+            for post in batch {
+                let (_, replied) =
+                    Post::html_body(post.plaintext_content.as_deref(), board, pool).await?;
+                for message in replied {
+                    message_ids.push(message);
+                    reply_ids.push(post.id);
+                    reply_threads.push(post.thread);
                 }
             }
-            .0;
 
-            let body = BOLD_RE.replace_all(&body, |c: &Captures| format!(r"<b>{}</b>", &c[2]));
-            let body = ITALIC_RE.replace_all(&body, |c: &Captures| format!(r"<em>{}</em>", &c[2]));
-            let replied: Vec<i32> = REPLY_RE
-                .captures_iter(&*body)
-                .map(|c| c[1].parse().unwrap())
-                .collect();
+            query!(
+                "INSERT INTO replies(message_id, message_board, reply_id, reply_board, reply_thread)
+                SELECT message_id, $1, reply_id, $1, reply_thread
+                FROM UNNEST($2::INTEGER[], $3::INTEGER[], $4::INTEGER[])
+                    AS r(message_id, reply_id, reply_thread)
+                ON CONFLICT DO NOTHING",
+                board,
+                &message_ids,
+                &reply_ids,
+                &reply_threads
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
 
-            let replied = query!(
-                "SELECT id, thread
+    async fn html_body(
+        body: Option<&str>,
+        board: &str,
+        pool: &PgPool,
+    ) -> Result<(String, Vec<i32>), sqlx::Error> {
+        if let Some(body) = body {
+            let segments = render_segments(body, pool).await?;
+            let referenced = referenced_posts(&segments);
+            let replied: Vec<(i32, i32)> = timed(
+                "html_body replies",
+                query!(
+                    "SELECT id, thread
                         FROM posts
                         WHERE id = ANY($1) AND board = $2",
-                &replied,
-                board
+                    &referenced,
+                    board
+                )
+                .fetch_all(pool),
             )
-            .fetch_all(pool)
-            .await?;
-
-            let body = REPLY_RE.replace_all(&*body, |c: &Captures| {
-                let id: i32 = c[1].parse().unwrap();
-                if let Some(r) = replied.iter().find(|r| r.id == id) {
-                    format!(
-                        r#"<a href="{}#{}">&gt;&gt;{}</a>"#,
-                        uri!(crate::routes::public::thread(board, r.thread)),
-                        &c[1],
-                        &c[1]
-                    )
-                } else {
-                    format!(r#"&gt;&gt;{}"#, &c[1])
-                }
-            });
-            Ok((
-                body.into_owned(),
-                replied.into_iter().map(|r| r.id).collect(),
-            ))
+            .await?
+            .into_iter()
+            .map(|r| (r.id, r.thread))
+            .collect();
+            let body = link_replies(&segments, board, &replied);
+            Ok((body, replied.into_iter().map(|(id, _)| id).collect()))
         } else {
-            Ok((
-                html! {
-                    .post-content {}
-                }
-                .0,
-                Vec::new(),
-            ))
+            Ok((String::new(), Vec::new()))
         }
     }
 
@@ -333,6 +1319,18 @@ impl Post {
         PreEscaped(self.html_content.as_ref())
     }
 
+    /// Get a reference to the post's unrendered text.
+    #[must_use]
+    pub fn plaintext_content(&self) -> Option<&str> {
+        self.plaintext_content.as_deref()
+    }
+
+    /// Whether the post has any text, as opposed to just an image.
+    #[must_use]
+    pub fn has_content(&self) -> bool {
+        self.plaintext_content.is_some()
+    }
+
     /// Get a reference to the post's title.
     #[must_use]
     pub fn title(&self) -> Option<&str> {
@@ -345,6 +1343,18 @@ impl Post {
         self.author.as_deref()
     }
 
+    /// Get a reference to the post's tripcode, without the leading `!`.
+    #[must_use]
+    pub fn tripcode(&self) -> Option<&str> {
+        self.tripcode.as_deref()
+    }
+
+    /// Get the address the post was made from, in its stored form.
+    #[must_use]
+    pub fn ip(&self) -> IpNetwork {
+        self.ip
+    }
+
     /// Get a reference to the post's email.
     #[must_use]
     pub fn email(&self) -> Option<&str> {
@@ -370,12 +1380,59 @@ impl Post {
     pub fn image(&self) -> Option<&Uuid> {
         self.image.as_ref()
     }
+
+    /// Whether the thread is closed to new replies.
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Whether the thread was pruned off its board into the archive.
+    pub fn archived(&self) -> bool {
+        self.archived
+    }
+
+    /// Get the hash of the poster token the post was made with, see [`poster_hash`].
+    /// Only for comparing against the viewer's own, it's never to be shown.
+    pub fn poster(&self) -> Option<&[u8]> {
+        self.poster.as_deref()
+    }
+
+    /// Whether the thread is currently pinned. Expired pins don't count.
+    pub fn is_sticky(&self) -> bool {
+        self.is_sticky
+            && self.sticky_until.map_or(true, |until| {
+                let now = sqlx::types::time::OffsetDateTime::now_utc();
+                until > PrimitiveDateTime::new(now.date(), now.time())
+            })
+    }
+}
+
+/// A thread as shown in a board's catalog.
+pub struct CatalogEntry {
+    op: Post,
+    replies: i64,
+    images: i64,
+}
+
+impl CatalogEntry {
+    pub fn op(&self) -> &Post {
+        &self.op
+    }
+
+    pub fn replies(&self) -> i64 {
+        self.replies
+    }
+
+    pub fn images(&self) -> i64 {
+        self.images
+    }
 }
 
 pub struct Reply {
     reply_id: i32,
     reply_board: String,
     reply_thread: i32,
+    poster: Option<Vec<u8>>,
 }
 
 impl Reply {
@@ -396,6 +1453,26 @@ impl Reply {
     pub fn thread(&self) -> i32 {
         self.reply_thread
     }
+
+    /// Get the poster token hash of the replying post, like [`Post::poster`].
+    pub fn poster(&self) -> Option<&[u8]> {
+        self.poster.as_deref()
+    }
+
+    /// Get the posts replying to this reply in turn.
+    pub async fn replies(&self, pool: &PgPool) -> Result<Vec<Reply>, sqlx::Error> {
+        query_as!(
+            Reply,
+            "SELECT reply_id, reply_board, reply_thread, posts.poster
+            FROM replies
+                JOIN posts ON (posts.id, posts.board) = (replies.reply_id, replies.reply_board)
+            WHERE message_id = $1 AND message_board = $2",
+            self.reply_id,
+            self.reply_board
+        )
+        .fetch_all(pool)
+        .await
+    }
 }
 
 pub struct NonEmptyStr<'s>(&'s str);
@@ -442,128 +1519,1356 @@ pub struct Image {
     hash: Uuid,
 }
 
-impl Image {
-    pub async fn from_buf(buf: &[u8], pool: &PgPool) -> Result<Image, Error> {
-        let hash = {
-            let hash = md5::compute(buf);
-            Uuid::from_bytes(hash.0)
-        };
-        let maybe = query!(
-            r#"SELECT CASE WHEN EXISTS (
-                SELECT hash FROM images WHERE hash = $1
-            ) THEN TRUE ELSE FALSE END as "exits!""#,
-            hash
-        )
-        .fetch_one(pool)
-        .await?
-        .exits;
-        if maybe {
-            Ok(Image { hash })
-        } else {
-            let mut file = tokio::fs::File::create(format!("./images/{hash}")).await?;
-            file.write_all(buf).await?;
-
-            let image = image::load_from_memory(buf)?;
-            let image = image.resize(200, 200, image::imageops::FilterType::Lanczos3);
-            let mut buf = Vec::new();
-            let encoder = image::codecs::png::PngEncoder::new(&mut buf);
-            encoder.write_image(
-                image.as_bytes(),
-                image.width(),
-                image.height(),
-                image.color(),
-            )?;
-
-            let mut file = tokio::fs::File::create(format!("./thumbs/{hash}.png")).await?;
-            file.write_all(&buf).await?;
-
-            query!("INSERT INTO images VALUES ($1)", hash)
-                .execute(pool)
-                .await?;
-            Ok(Image { hash })
-        }
-    }
+/// The type and thumbnail extension of an image, see [`Image::media_for`].
+pub struct ImageMedia {
+    media_type: Option<String>,
+    thumb_ext: String,
+}
 
-    pub fn hash(&self) -> Uuid {
-        self.hash
+impl ImageMedia {
+    /// Get the MIME type of the upload, if it was recorded.
+    /// Images from before types were recorded have none.
+    #[must_use]
+    pub fn media_type(&self) -> Option<&str> {
+        self.media_type.as_deref()
     }
 
-    pub fn uri(&self) -> String {
-        format!("/images/{}", self.hash)
+    /// Get the extension the image's thumbnail is stored with.
+    #[must_use]
+    pub fn thumb_ext(&self) -> &str {
+        self.thumb_ext.as_ref()
     }
 }
 
-#[derive(FromForm, Debug)]
-pub struct PostForm<'r> {
-    pub title: Option<NonEmptyStr<'r>>,
-    pub author: Option<NonEmptyStr<'r>>,
-    pub email: Option<NonEmptyStr<'r>>,
-    pub sage: bool,
-    pub content: Option<NonEmptyStr<'r>>,
-    pub thread: Option<i32>,
-    pub board: NonEmptyStr<'r>,
-    pub image: Option<Bytes>,
-    pub captcha: Option<NonEmptyStr<'r>>,
+/// A post an image is attached to, see [`Image::uses_of`].
+pub struct ImageUse {
+    id: i32,
+    board: String,
+    thread: i32,
+    posted_at: PrimitiveDateTime,
 }
 
-impl<'r> PostForm<'r> {
-    pub fn captcha(&self) -> Option<&str> {
-        self.captcha.as_deref()
+impl ImageUse {
+    /// Get the post's id.
+    #[must_use]
+    pub fn id(&self) -> i32 {
+        self.id
     }
-}
-
-#[derive(FromForm, Debug)]
-pub struct BoardForm<'r> {
-    pub name: NonEmptyStr<'r>,
-    pub title: NonEmptyStr<'r>,
-}
 
-#[derive(Debug)]
-pub struct Bytes(Vec<u8>);
+    /// Get a reference to the post's board.
+    #[must_use]
+    pub fn board(&self) -> &str {
+        self.board.as_ref()
+    }
 
-impl Deref for Bytes {
-    type Target = [u8];
+    /// Get the post's thread.
+    #[must_use]
+    pub fn thread(&self) -> i32 {
+        self.thread
+    }
 
-    fn deref(&self) -> &Self::Target {
-        self.0.deref()
+    /// Get the time the post was made.
+    #[must_use]
+    pub fn posted_at(&self) -> &PrimitiveDateTime {
+        &self.posted_at
     }
 }
 
-#[async_trait]
-impl<'v> FromFormField<'v> for Bytes {
-    async fn from_data(field: rocket::form::DataField<'v, '_>) -> rocket::form::Result<'v, Self> {
-        let stream = field.data.open(10.mebibytes());
-        let buf = stream
-            .into_bytes()
-            .await
-            .map(|v| v.into_inner())
-            .map_err(|e| rocket::form::Errors::from(rocket::form::Error::custom(e)))?;
-        if buf.is_empty() {
-            Err(rocket::form::Error::validation("Empty files are not allowed").into())
+/// What an upload turned out to be, going by its magic bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Media {
+    Image(ImageFormat),
+    Video(VideoFormat),
+}
+
+/// Videos are only taken when `FFMPEG_PATH` is set, since ffmpeg is what makes their thumbnails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFormat {
+    WebM,
+    Mp4,
+}
+
+impl VideoFormat {
+    fn guess(buf: &[u8]) -> Option<Self> {
+        if buf.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+            Some(Self::WebM)
+        } else if buf.get(4..8) == Some(b"ftyp") {
+            Some(Self::Mp4)
         } else {
-            Ok(Self(buf))
+            None
         }
     }
 
-    fn from_value(field: rocket::form::ValueField<'v>) -> rocket::form::Result<'v, Self> {
-        Ok(Self(field.value.as_bytes().to_owned()))
+    fn extensions_str(self) -> &'static [&'static str] {
+        match self {
+            Self::WebM => &["webm"],
+            Self::Mp4 => &["mp4", "m4v"],
+        }
     }
+}
 
-    fn default() -> Option<Self> {
-        None
+impl Media {
+    /// The MIME type recorded for the upload.
+    fn mime(self) -> String {
+        let ext = match self {
+            Media::Image(format) => format.extensions_str()[0],
+            Media::Video(format) => format.extensions_str()[0],
+        };
+        ContentType::from_extension(ext)
+            .unwrap_or(ContentType::Binary)
+            .to_string()
     }
 }
 
-#[derive(Debug)]
-pub struct NotBanned;
-
-#[async_trait]
+impl Image {
+    /// Store an upload to `board`, or find the same file already stored.
+    pub async fn from_buf(file: &Bytes, board: &Board, pool: &PgPool) -> Result<Image, Error> {
+        let too_large = board
+            .max_upload_bytes()
+            .map_or(false, |max| file.len() as i64 > max);
+        if !file.is_complete() || too_large {
+            return Err(Error::UploadTooLarge);
+        }
+        let media = Image::sniff(file)?;
+        if let Some(allowed) = board.allowed_mime_types() {
+            let mime = media.mime();
+            if !allowed.iter().any(|t| *t == mime) {
+                return Err(Error::DisallowedFileType);
+            }
+        }
+        let buf: &[u8] = file;
+        let digest = Sha256::digest(buf);
+        let hash = {
+            let mut bytes = [0; 16];
+            bytes.copy_from_slice(&digest[..16]);
+            Uuid::from_bytes(bytes)
+        };
+        // Images from before the switch to SHA-256 were named, and so blocked, by their MD5.
+        let legacy_hash = Uuid::from_bytes(md5::compute(buf).0);
+        if Image::is_blocked(hash, pool).await? || Image::is_blocked(legacy_hash, pool).await? {
+            return Err(Error::BlockedContent);
+        }
+        let size = buf.len() as i64;
+        let existing = query!("SELECT sha256 FROM images WHERE hash = $1", hash)
+            .fetch_optional(pool)
+            .await?;
+        if let Some(existing) = existing {
+            // The name only holds half of the digest, so check that it's really the same file.
+            if existing.sha256.as_deref() == Some(&digest[..]) {
+                Ok(Image { hash })
+            } else {
+                tracing::error!(%hash, "Upload collides with a different stored image");
+                Err(Error::HashCollision)
+            }
+        } else {
+            if let Some(quota) = CONFIG.storage_quota_bytes {
+                let used = query!(
+                    r#"SELECT CAST(COALESCE(SUM(size), 0) AS BIGINT) AS "used!" FROM images"#
+                )
+                .fetch_one(pool)
+                .await?
+                .used;
+                if used + size > quota {
+                    return Err(Error::StorageQuotaExceeded);
+                }
+            }
+
+            let (image, stored) = match media {
+                Media::Image(format) => {
+                    let mut image = image::load_from_memory_with_format(buf, format)?;
+                    let orientation = exif_orientation(buf);
+                    if CONFIG.fix_orientation {
+                        image = apply_orientation(image, orientation);
+                    }
+                    let stored = strip_metadata(buf, format, orientation, &image)?;
+                    (image, stored)
+                }
+                // ffmpeg needs the video on disk, so it gets a temporary copy
+                // that's gone again whether or not a poster came out of it.
+                Media::Video(_) => {
+                    let path = std::env::temp_dir().join(format!("ruburu-{hash}"));
+                    tokio::fs::write(&path, buf).await?;
+                    let poster = video_poster(&path).await;
+                    tokio::fs::remove_file(&path).await?;
+                    (poster?, Cow::Borrowed(buf))
+                }
+            };
+            STORAGE
+                .put(Folder::Images, &hash.to_string(), &media.mime(), &stored)
+                .await?;
+
+            let thumb = thumbnail(&image);
+            let source = match media {
+                Media::Image(format) => Some(format),
+                Media::Video(_) => None,
+            };
+            let mut thumb_format = thumb_format(&thumb, source);
+            // An image that's already thumbnail sized is its own thumbnail,
+            // GIFs included so that small animations stay animated.
+            let encoded;
+            let buf: &[u8] = match (&thumb, source) {
+                (Cow::Borrowed(_), Some(format))
+                    if format == thumb_format || format == ImageFormat::Gif =>
+                {
+                    thumb_format = format;
+                    &stored
+                }
+                (_, Some(ImageFormat::Gif)) => {
+                    encoded = match animated_thumbnail(buf)? {
+                        Some(animated) => {
+                            thumb_format = ImageFormat::Gif;
+                            animated
+                        }
+                        None => encode_image(&thumb, thumb_format, CONFIG.thumb_jpeg_quality)?,
+                    };
+                    &encoded
+                }
+                _ => {
+                    encoded = encode_image(&thumb, thumb_format, CONFIG.thumb_jpeg_quality)?;
+                    &encoded
+                }
+            };
+            let thumb_ext = thumb_format.extensions_str()[0];
+            let thumb_mime = ContentType::from_extension(thumb_ext).unwrap_or(ContentType::Binary);
+            STORAGE
+                .put(
+                    Folder::Thumbs,
+                    &format!("{hash}.{thumb_ext}"),
+                    &thumb_mime.to_string(),
+                    buf,
+                )
+                .await?;
+
+            query!(
+                "INSERT INTO images(hash, size, thumb_ext, media_type, sha256)
+                VALUES ($1, $2, $3, $4, $5)",
+                hash,
+                stored.len() as i64,
+                thumb_ext,
+                media.mime(),
+                &digest[..]
+            )
+            .execute(pool)
+            .await?;
+            Ok(Image { hash })
+        }
+    }
+
+    /// Detect the format from the file's magic bytes, and make sure it's allowed
+    /// and agrees with the content type the client claimed.
+    fn sniff(file: &Bytes) -> Result<Media, Error> {
+        let (media, extensions) = match image::guess_format(file) {
+            Ok(format) if CONFIG.allowed_image_formats.contains(&format) => {
+                (Media::Image(format), format.extensions_str())
+            }
+            Ok(_) => return Err(Error::UnsupportedImageType),
+            Err(_) => match VideoFormat::guess(file) {
+                Some(format) if CONFIG.ffmpeg.is_some() => {
+                    (Media::Video(format), format.extensions_str())
+                }
+                _ => return Err(Error::UnsupportedImageType),
+            },
+        };
+        match file.content_type() {
+            Some(declared) if *declared != ContentType::Binary => {
+                let matches = extensions
+                    .iter()
+                    .filter_map(|ext| ContentType::from_extension(ext))
+                    .any(|ct| ct == *declared);
+                if matches {
+                    Ok(media)
+                } else {
+                    Err(Error::UnsupportedImageType)
+                }
+            }
+            _ => Ok(media),
+        }
+    }
+
+    /// Get what's needed to render each of the images `hashes` in one query, keyed by hash.
+    pub async fn media_for(
+        hashes: &[Uuid],
+        pool: &PgPool,
+    ) -> Result<HashMap<Uuid, ImageMedia>, sqlx::Error> {
+        Ok(query!(
+            "SELECT hash, media_type, thumb_ext FROM images WHERE hash = ANY($1)",
+            hashes
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|r| {
+            let media = ImageMedia {
+                media_type: r.media_type,
+                thumb_ext: r.thumb_ext,
+            };
+            (r.hash, media)
+        })
+        .collect())
+    }
+
+    /// Get every post that has one of the images `hashes` attached in one query,
+    /// keyed by hash, oldest first.
+    pub async fn uses_of(
+        hashes: &[Uuid],
+        pool: &PgPool,
+    ) -> Result<HashMap<Uuid, Vec<ImageUse>>, sqlx::Error> {
+        let rows = query!(
+            "SELECT DISTINCT post_images.image, posts.id, posts.board, posts.thread, posts.posted_at
+            FROM post_images
+                JOIN posts ON (posts.id, posts.board) = (post_images.post_id, post_images.board)
+            WHERE post_images.image = ANY($1) AND posts.deleted_at IS NULL
+            ORDER BY posts.posted_at",
+            hashes
+        )
+        .fetch_all(pool)
+        .await?;
+        let mut uses: HashMap<Uuid, Vec<ImageUse>> = HashMap::new();
+        for row in rows {
+            uses.entry(row.image).or_default().push(ImageUse {
+                id: row.id,
+                board: row.board,
+                thread: row.thread,
+                posted_at: row.posted_at,
+            });
+        }
+        Ok(uses)
+    }
+
+    pub async fn is_blocked(hash: Uuid, pool: &PgPool) -> Result<bool, sqlx::Error> {
+        Ok(query!(
+            r#"SELECT EXISTS (
+                SELECT hash FROM blocked_hashes WHERE hash = $1
+            ) as "blocked!""#,
+            hash
+        )
+        .fetch_one(pool)
+        .await?
+        .blocked)
+    }
+
+    /// Reject any future uploads with this hash.
+    pub async fn block(hash: Uuid, pool: &PgPool) -> Result<(), sqlx::Error> {
+        query!(
+            "INSERT INTO blocked_hashes(hash) VALUES ($1) ON CONFLICT DO NOTHING",
+            hash
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    pub fn hash(&self) -> Uuid {
+        self.hash
+    }
+
+    pub fn uri(&self) -> String {
+        Image::src(self.hash)
+    }
+
+    /// Where to load the full file for `hash` from.
+    pub fn src(hash: Uuid) -> String {
+        STORAGE.url(Folder::Images, &hash.to_string())
+    }
+
+    /// Where to load the thumbnail for `hash` from.
+    /// Thumbnails no larger than `INLINE_THUMB_MAX_BYTES` are embedded as a `data:` URI
+    /// to save a request, anything else is loaded from storage.
+    /// Inlining reads the thumbnail on every render, which adds up with remote storage.
+    pub async fn thumb_src(hash: Uuid, pool: &PgPool) -> Result<String, Error> {
+        let ext = query!("SELECT thumb_ext FROM images WHERE hash = $1", hash)
+            .fetch_optional(pool)
+            .await?
+            .map_or_else(|| "png".to_string(), |i| i.thumb_ext);
+        Image::thumb_src_for(hash, &ext).await
+    }
+
+    /// Like [`Image::thumb_src`], for a thumbnail whose extension is already known.
+    pub async fn thumb_src_for(hash: Uuid, ext: &str) -> Result<String, Error> {
+        let name = format!("{hash}.{ext}");
+        if let Some(max) = CONFIG.inline_thumb_max_bytes {
+            let thumb = STORAGE.get(Folder::Thumbs, &name).await?;
+            if thumb.len() as u64 <= max {
+                let mime = ContentType::from_extension(ext).unwrap_or(ContentType::PNG);
+                return Ok(format!("data:{mime};base64,{}", base64::encode(thumb)));
+            }
+        }
+        Ok(STORAGE.url(Folder::Thumbs, &name))
+    }
+}
+
+/// Record `images` as the attachments of post `id`, keeping their order.
+async fn attach_images(
+    tx: &mut Transaction<'_, Postgres>,
+    board: &str,
+    id: i32,
+    images: &[Image],
+) -> Result<(), sqlx::Error> {
+    for (position, image) in images.iter().enumerate() {
+        query!(
+            "INSERT INTO post_images(board, post_id, position, image) VALUES ($1, $2, $3, $4)",
+            board,
+            id,
+            position as i16,
+            image.hash
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Board stylesheets end up inside a `<style>` element, so they must not be able to close it.
+/// `@import` is refused too, since it would pull in styles that never went through this check.
+/// CSS escapes are resolved before looking for it, `@\69mport` is the same rule to a browser.
+fn check_css(css: &str) -> Result<(), Error> {
+    if css.len() > CONFIG.custom_css_max_bytes {
+        return Err(Error::InvalidCss("it's too long"));
+    }
+    if css.contains('<') {
+        return Err(Error::InvalidCss("`<` isn't allowed"));
+    }
+    let unescaped = CSS_ESCAPE_RE.replace_all(css, |caps: &Captures| {
+        u32::from_str_radix(&caps[1], 16)
+            .ok()
+            .and_then(char::from_u32)
+            .map_or_else(String::new, String::from)
+    });
+    if unescaped
+        .replace('\\', "")
+        .to_ascii_lowercase()
+        .contains("@import")
+    {
+        return Err(Error::InvalidCss("`@import` isn't allowed"));
+    }
+    Ok(())
+}
+
+/// Zero-width, bidi control and other invisible characters that can be used to dodge filters or garble layout.
+/// ZWNJ and ZWJ are left alone, since Persian and Indic scripts use them to control how letters join
+/// and emoji sequences need ZWJ.
+fn is_invisible(c: char) -> bool {
+    matches!(c,
+        '\u{00AD}'
+        | '\u{200B}'
+        | '\u{200E}'..='\u{200F}'
+        | '\u{202A}'..='\u{202E}'
+        | '\u{2060}'..='\u{2064}'
+        | '\u{2066}'..='\u{2069}'
+        | '\u{FEFF}')
+        || (c.is_control() && !matches!(c, '\n' | '\r' | '\t'))
+}
+
+/// Apply the `INVISIBLE_CHARS` policy to a piece of user input.
+/// Input that is nothing but invisible characters becomes `None`.
+pub fn clean_text(text: Option<&str>) -> Result<Option<Cow<'_, str>>, Error> {
+    clean_text_with(text, CONFIG.invisible_chars)
+}
+
+fn clean_text_with(
+    text: Option<&str>,
+    policy: InvisibleChars,
+) -> Result<Option<Cow<'_, str>>, Error> {
+    let text = match text {
+        Some(text) => text,
+        None => return Ok(None),
+    };
+    if !text.chars().any(is_invisible) {
+        return Ok(Some(Cow::Borrowed(text)));
+    }
+    match policy {
+        InvisibleChars::Allow => Ok(Some(Cow::Borrowed(text))),
+        InvisibleChars::Reject => Err(Error::InvisibleCharacters),
+        InvisibleChars::Strip => {
+            let stripped: String = text.chars().filter(|c| !is_invisible(*c)).collect();
+            Ok((!stripped.trim().is_empty()).then(|| Cow::Owned(stripped)))
+        }
+    }
+}
+
+/// Normalize a new board's `name`, failing with `InvalidBoardName` unless it's
+/// 1 to 16 letters and digits once lowercased, and not one of `RESERVED_BOARD_NAMES`.
+fn board_name(name: &str) -> Result<String, Error> {
+    let name = name.trim().to_lowercase();
+    let valid = (1..=16).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit());
+    if !valid || RESERVED_BOARD_NAMES.contains(&name.as_str()) {
+        return Err(Error::InvalidBoardName);
+    }
+    Ok(name)
+}
+
+/// Reject `value` if it's longer than `max` characters, naming the form `field` it came from.
+fn check_length(field: &'static str, value: Option<&str>, max: usize) -> Result<(), Error> {
+    match value {
+        Some(value) if value.chars().count() > max => Err(Error::TooLong(field, max)),
+        _ => Ok(()),
+    }
+}
+
+/// Names have their own limit, checked by [`parse_author`] once the tripcode secret is split off.
+fn check_post_lengths(
+    title: Option<&str>,
+    email: Option<&str>,
+    content: Option<&str>,
+) -> Result<(), Error> {
+    check_length("Subjects", title, CONFIG.subject_max_chars)?;
+    check_length("Emails", email, CONFIG.email_max_chars)?;
+    check_length("Posts", content, CONFIG.content_max_chars)
+}
+
+fn check_board_lengths(title: &str, description: Option<&str>) -> Result<(), Error> {
+    check_length("Board titles", Some(title), CONFIG.board_title_max_chars)?;
+    check_length(
+        "Board descriptions",
+        description,
+        CONFIG.board_description_max_chars,
+    )
+}
+
+/// Split an author field into the name and, if it's followed by `#secret`, the secret's tripcode.
+/// The secret itself is never stored or shown. A field that's only a secret leaves the post anonymous.
+pub fn parse_author(author: Option<&str>) -> Result<(Option<&str>, Option<String>), Error> {
+    let (name, secret) = match author {
+        Some(author) => match author.split_once('#') {
+            Some((name, secret)) => (name.trim(), Some(secret)),
+            None => (author.trim(), None),
+        },
+        None => return Ok((None, None)),
+    };
+    if name.chars().any(char::is_control) {
+        return Err(Error::InvisibleCharacters);
+    }
+    if name.chars().count() > CONFIG.author_max_chars {
+        return Err(Error::AuthorTooLong);
+    }
+    let name = (!name.is_empty()).then(|| name);
+    let tripcode = secret.filter(|s| !s.is_empty()).map(tripcode);
+    Ok((name, tripcode))
+}
+
+/// The classic imageboard tripcode: DES crypt of the HTML escaped secret,
+/// salted with its second and third characters.
+fn tripcode(secret: &str) -> String {
+    let key = secret
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    let salt: String = format!("{key}H.")
+        .chars()
+        .skip(1)
+        .take(2)
+        .map(|c| match c {
+            ':'..='@' => (b'A' + (c as u8 - b':')) as char,
+            '['..='`' => (b'a' + (c as u8 - b'[')) as char,
+            '.'..='z' => c,
+            _ => '.',
+        })
+        .collect();
+    let hash = pwhash::unix_crypt::hash_with(salt.as_str(), key.as_bytes())
+        .expect("the salt only has valid characters");
+    hash[hash.len() - 10..].to_owned()
+}
+
+/// Await `fut`, warning about it if it takes longer than `SLOW_QUERY_MS`.
+async fn timed<F: Future>(name: &'static str, fut: F) -> F::Output {
+    let threshold = match CONFIG.slow_query {
+        Some(threshold) => threshold,
+        None => return fut.await,
+    };
+    let start = Instant::now();
+    let out = fut.instrument(tracing::debug_span!("query", name)).await;
+    let elapsed = start.elapsed();
+    if elapsed > threshold {
+        tracing::warn!(
+            query = name,
+            elapsed_ms = elapsed.as_millis() as u64,
+            "slow query"
+        );
+    }
+    out
+}
+
+/// Find `/name/` mentions that stand on their own, i.e. aren't part of a path or a `>>>/b/1` marker.
+fn board_mentions(body: &str) -> Vec<(std::ops::Range<usize>, &str)> {
+    BOARD_RE
+        .captures_iter(body)
+        .filter_map(|c| {
+            let m = c.get(0).unwrap();
+            let before = body[..m.start()].chars().next_back();
+            let after = body[m.end()..].chars().next();
+            let before_ok = before.map_or(true, |c| c.is_whitespace() || c == '>' || c == '(');
+            let after_ok = after.map_or(true, |c| c.is_whitespace() || "<).,!?:;".contains(c));
+            (before_ok && after_ok).then(|| (m.range(), c.get(1).unwrap().as_str()))
+        })
+        .collect()
+}
+
+/// Render a board description with the same markup as posts, less code blocks and reply links.
+async fn render_description(description: &str, pool: &PgPool) -> Result<String, sqlx::Error> {
+    let lines: Vec<&str> = description.lines().collect();
+    format_text(&lines, pool).await
+}
+
+/// Render a post `body` as pairs of markup and whether it's a code block.
+/// Blocks fenced with ``` are shown verbatim, an unterminated fence is left as text.
+async fn render_segments(body: &str, pool: &PgPool) -> Result<Vec<(String, bool)>, sqlx::Error> {
+    let lines: Vec<&str> = body.lines().collect();
+    let mut fences: Vec<usize> = (0..lines.len())
+        .filter(|&i| lines[i].starts_with("```"))
+        .collect();
+    fences.truncate(fences.len() - fences.len() % 2);
+
+    let mut segments: Vec<(String, bool)> = Vec::new();
+    let mut start = 0;
+    for fence in fences.chunks(2) {
+        let (open, close) = (fence[0], fence[1]);
+        segments.push((format_text(&lines[start..open], pool).await?, false));
+        let code = html! { pre { code { (lines[open + 1..close].join("\n")) } } };
+        segments.push((code.0, true));
+        start = close + 1;
+    }
+    segments.push((format_text(&lines[start..], pool).await?, false));
+    Ok(segments)
+}
+
+/// The posts referenced with `>>` outside of code blocks, in order of first mention.
+/// Only the first `MAX_REPLY_LINKS` count, any past that stay plain text.
+fn referenced_posts(segments: &[(String, bool)]) -> Vec<i32> {
+    let mut referenced: Vec<i32> = Vec::new();
+    for id in segments
+        .iter()
+        .filter(|(_, code)| !code)
+        .flat_map(|(text, _)| REPLY_RE.captures_iter(text))
+        .filter_map(|c| c[1].parse().ok())
+    {
+        if referenced.len() >= CONFIG.max_reply_links {
+            break;
+        }
+        if !referenced.contains(&id) {
+            referenced.push(id);
+        }
+    }
+    referenced
+}
+
+/// Join rendered `segments` back up, linking `>>` references to the posts in `replied`,
+/// given as pairs of id and thread. References to anything else stay plain text.
+fn link_replies(segments: &[(String, bool)], board: &str, replied: &[(i32, i32)]) -> String {
+    let mut body = String::new();
+    for (text, code) in segments {
+        if *code {
+            body.push_str(text);
+            continue;
+        }
+        let text = REPLY_RE.replace_all(text, |c: &Captures| {
+            let found = c[1]
+                .parse::<i32>()
+                .ok()
+                .and_then(|id| replied.iter().find(|(r, _)| *r == id));
+            if let Some((_, thread)) = found {
+                format!(
+                    r#"<a href="{}#{}">&gt;&gt;{}</a>"#,
+                    uri!(crate::routes::public::thread(board, *thread, _)),
+                    &c[1],
+                    &c[1]
+                )
+            } else {
+                format!(r#"&gt;&gt;{}"#, &c[1])
+            }
+        });
+        body.push_str(&text);
+    }
+    body
+}
+
+/// Render plain post `lines`, with greentext and inline markup but without reply links.
+async fn format_text(lines: &[&str], pool: &PgPool) -> Result<String, sqlx::Error> {
+    let body = html! {
+        @for line in lines {
+            @if line.starts_with('>') && line.chars().nth(1) != Some('>') {
+                .green-text { (line) }
+            } @else { (line) }
+            br;
+        }
+    }
+    .0;
+
+    let body = SPOILER_RE.replace_all(&body, |c: &Captures| {
+        format!(r#"<span class="spoiler">{}</span>"#, &c[1])
+    });
+    let body = BOLD_RE.replace_all(&body, |c: &Captures| format!(r"<b>{}</b>", &c[2]));
+    let body = ITALIC_RE.replace_all(&body, |c: &Captures| format!(r"<em>{}</em>", &c[2]));
+    Ok(if CONFIG.linkify_boards {
+        linkify_boards(&body, pool).await?
+    } else {
+        body.into_owned()
+    })
+}
+
+/// Link mentions of existing boards to their index page.
+async fn linkify_boards(body: &str, pool: &PgPool) -> Result<String, sqlx::Error> {
+    let mentions = board_mentions(body);
+    if mentions.is_empty() {
+        return Ok(body.to_owned());
+    }
+    let names: Vec<String> = mentions.iter().map(|(_, n)| n.to_string()).collect();
+    let existing: Vec<String> = query!("SELECT name FROM boards WHERE name = ANY($1)", &names)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|r| r.name)
+        .collect();
+
+    let mut linked = String::with_capacity(body.len());
+    let mut last = 0;
+    for (range, name) in mentions {
+        if existing.iter().any(|e| e == name) {
+            linked.push_str(&body[last..range.start]);
+            linked.push_str(&format!(
+                r#"<a href="{}">/{}/</a>"#,
+                uri!(crate::routes::public::board(name, _, _, _)),
+                name
+            ));
+            last = range.end;
+        }
+    }
+    linked.push_str(&body[last..]);
+    Ok(linked)
+}
+
+/// Read the EXIF orientation tag, defaulting to 1 (upright) if there's none.
+fn exif_orientation(buf: &[u8]) -> u32 {
+    exif::Reader::new()
+        .read_from_container(&mut std::io::Cursor::new(buf))
+        .ok()
+        .and_then(|exif| {
+            exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+                .and_then(|f| f.value.get_uint(0))
+        })
+        .unwrap_or(1)
+}
+
+/// Grab the first frame of the video at `path` with ffmpeg.
+async fn video_poster(path: &Path) -> Result<DynamicImage, Error> {
+    let ffmpeg = CONFIG.ffmpeg.as_ref().ok_or(Error::UnsupportedImageType)?;
+    let output = tokio::process::Command::new(ffmpeg)
+        .args(["-v", "error", "-i"])
+        .arg(path)
+        .args(["-frames:v", "1", "-f", "image2pipe", "-vcodec", "png", "-"])
+        .kill_on_drop(true)
+        .output()
+        .await?;
+    if !output.status.success() || output.stdout.is_empty() {
+        tracing::warn!(
+            "ffmpeg couldn't read {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return Err(Error::InvalidVideo);
+    }
+    Ok(image::load_from_memory_with_format(
+        &output.stdout,
+        ImageFormat::Png,
+    )?)
+}
+
+/// Quality used when a full-size JPEG has to be re-encoded.
+const REENCODE_JPEG_QUALITY: u8 = 95;
+
+/// Get a copy of the upload `buf` without EXIF, XMP, IPTC or text metadata.
+/// The segments holding them are cut out of JPEGs and PNGs, leaving the image data untouched,
+/// but a rotated image is re-encoded upright since its orientation tag goes too.
+/// Files that can't be taken apart are re-encoded, and other formats are kept as they are.
+fn strip_metadata<'b>(
+    buf: &'b [u8],
+    format: ImageFormat,
+    orientation: u32,
+    image: &DynamicImage,
+) -> Result<Cow<'b, [u8]>, image::ImageError> {
+    let stripped = match format {
+        ImageFormat::Jpeg | ImageFormat::Png if orientation != 1 => None,
+        ImageFormat::Jpeg => strip_jpeg(buf),
+        ImageFormat::Png => strip_png(buf),
+        _ => return Ok(Cow::Borrowed(buf)),
+    };
+    if let Some(stripped) = stripped {
+        return Ok(Cow::Owned(stripped));
+    }
+    // `image` is only upright already if `FIX_ORIENTATION` rotated it.
+    let image = if CONFIG.fix_orientation {
+        Cow::Borrowed(image)
+    } else {
+        Cow::Owned(apply_orientation(image.clone(), orientation))
+    };
+    let buf = encode_image(&image, format, REENCODE_JPEG_QUALITY)?;
+    Ok(Cow::Owned(buf))
+}
+
+/// Drop the APP1 (EXIF, XMP), APP13 (IPTC) and comment segments of a JPEG.
+fn strip_jpeg(buf: &[u8]) -> Option<Vec<u8>> {
+    if !buf.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut out = vec![0xFF, 0xD8];
+    let mut pos = 2;
+    loop {
+        let marker = *buf.get(pos + 1)?;
+        if buf[pos] != 0xFF {
+            return None;
+        }
+        match marker {
+            // Start of scan, everything after it is image data.
+            0xDA => {
+                out.extend_from_slice(&buf[pos..]);
+                return Some(out);
+            }
+            0x01 | 0xD0..=0xD7 => {
+                out.extend_from_slice(&buf[pos..pos + 2]);
+                pos += 2;
+            }
+            _ => {
+                let len = u16::from_be_bytes([*buf.get(pos + 2)?, *buf.get(pos + 3)?]) as usize;
+                let segment = buf.get(pos..pos + 2 + len)?;
+                if !matches!(marker, 0xE1 | 0xED | 0xFE) {
+                    out.extend_from_slice(segment);
+                }
+                pos += 2 + len;
+            }
+        }
+    }
+}
+
+/// Drop the `eXIf`, text and timestamp chunks of a PNG.
+fn strip_png(buf: &[u8]) -> Option<Vec<u8>> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    if !buf.starts_with(SIGNATURE) {
+        return None;
+    }
+    let mut out = SIGNATURE.to_vec();
+    let mut pos = SIGNATURE.len();
+    loop {
+        let len = u32::from_be_bytes(buf.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let kind = buf.get(pos + 4..pos + 8)?;
+        // Length, type, data and CRC.
+        let chunk = buf.get(pos..pos + 12 + len)?;
+        if !matches!(kind, b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt" | b"tIME") {
+            out.extend_from_slice(chunk);
+        }
+        if kind == b"IEND" {
+            return Some(out);
+        }
+        pos += 12 + len;
+    }
+}
+
+/// Encode `image` as `format`, which must be JPEG or PNG.
+fn encode_image(
+    image: &DynamicImage,
+    format: ImageFormat,
+    jpeg_quality: u8,
+) -> Result<Vec<u8>, image::ImageError> {
+    let mut buf = Vec::new();
+    if format == ImageFormat::Jpeg {
+        let image = image.to_rgb8();
+        let mut encoder =
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buf, jpeg_quality);
+        encoder.encode(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            image::ColorType::Rgb8,
+        )?;
+    } else {
+        let encoder = image::codecs::png::PngEncoder::new(&mut buf);
+        encoder.write_image(
+            image.as_bytes(),
+            image.width(),
+            image.height(),
+            image.color(),
+        )?;
+    }
+    Ok(buf)
+}
+
+/// Rotate and flip `image` so that it's upright for the given EXIF orientation.
+fn apply_orientation(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Shrink `image` to fit the thumbnail box,
+/// keeping its sides within `THUMB_MAX_ASPECT` of each other according to `THUMB_FIT`.
+/// Images that need none of that are passed through borrowed.
+fn thumbnail(image: &DynamicImage) -> Cow<'_, DynamicImage> {
+    thumbnail_with(
+        image,
+        CONFIG.thumb_fit,
+        CONFIG.thumb_max_aspect,
+        CONFIG.thumb_max_px,
+    )
+}
+
+fn thumbnail_with(
+    image: &DynamicImage,
+    fit: ThumbFit,
+    max_aspect: f32,
+    size: u32,
+) -> Cow<'_, DynamicImage> {
+    let (width, height) = image.dimensions();
+    let aspect = width as f32 / height as f32;
+
+    match fit {
+        ThumbFit::Crop if aspect > max_aspect => {
+            let cropped = (height as f32 * max_aspect) as u32;
+            let image = image.crop_imm((width - cropped) / 2, 0, cropped, height);
+            Cow::Owned(shrink_to(&image, size).into_owned())
+        }
+        ThumbFit::Crop if 1.0 / aspect > max_aspect => {
+            let cropped = (width as f32 * max_aspect) as u32;
+            let image = image.crop_imm(0, (height - cropped) / 2, width, cropped);
+            Cow::Owned(shrink_to(&image, size).into_owned())
+        }
+        ThumbFit::Pad => {
+            let thumb = shrink_to(image, size);
+            let (width, height) = thumb.dimensions();
+            let min_side = (width.max(height) as f32 / max_aspect).ceil() as u32;
+            if width >= min_side && height >= min_side {
+                return thumb;
+            }
+            let mut canvas = DynamicImage::new_rgba8(width.max(min_side), height.max(min_side));
+            let x = (canvas.width() - width) / 2;
+            let y = (canvas.height() - height) / 2;
+            image::imageops::overlay(&mut canvas, &*thumb, x.into(), y.into());
+            Cow::Owned(canvas)
+        }
+        _ => shrink_to(image, size),
+    }
+}
+
+/// Shrink every frame of an animated GIF, keeping their delays.
+/// GIFs with a single frame get a still thumbnail instead, so this returns `None` for them.
+fn animated_thumbnail(buf: &[u8]) -> Result<Option<Vec<u8>>, image::ImageError> {
+    let frames = image::codecs::gif::GifDecoder::new(std::io::Cursor::new(buf))?
+        .into_frames()
+        .collect_frames()?;
+    if frames.len() < 2 {
+        return Ok(None);
+    }
+    let mut out = Vec::new();
+    {
+        let mut encoder = image::codecs::gif::GifEncoder::new(&mut out);
+        encoder.set_repeat(image::codecs::gif::Repeat::Infinite)?;
+        encoder.encode_frames(frames.into_iter().map(|frame| {
+            let delay = frame.delay();
+            let image = DynamicImage::ImageRgba8(frame.into_buffer());
+            image::Frame::from_parts(thumbnail(&image).to_rgba8(), 0, 0, delay)
+        }))?;
+    }
+    Ok(Some(out))
+}
+
+/// Pick the format to save a thumbnail of a `source` image in according to `THUMB_FORMAT`.
+fn thumb_format(thumb: &DynamicImage, source: Option<ImageFormat>) -> ImageFormat {
+    thumb_format_with(thumb, source, CONFIG.thumb_format)
+}
+
+/// `auto` keeps PNGs and anything else with see-through pixels lossless and makes the rest JPEG,
+/// which is far smaller for photos. Video posters have no source format.
+fn thumb_format_with(
+    thumb: &DynamicImage,
+    source: Option<ImageFormat>,
+    setting: ThumbFormat,
+) -> ImageFormat {
+    match setting {
+        ThumbFormat::Auto if source == Some(ImageFormat::Png) || is_transparent(thumb) => {
+            ImageFormat::Png
+        }
+        ThumbFormat::Auto | ThumbFormat::Jpeg => ImageFormat::Jpeg,
+        ThumbFormat::Png => ImageFormat::Png,
+    }
+}
+
+/// Whether any of `image`'s pixels are see-through. Having an alpha channel isn't enough,
+/// GIFs and WebPs decode with one whether or not they use it.
+fn is_transparent(image: &DynamicImage) -> bool {
+    image.color().has_alpha() && image.to_rgba8().pixels().any(|p| p[3] < u8::MAX)
+}
+
+/// Scale `image` down to fit within `size` pixels on each side. Images that already fit
+/// are left at their size, upscaling them would only make them blurry.
+fn shrink_to(image: &DynamicImage, size: u32) -> Cow<'_, DynamicImage> {
+    let (width, height) = image.dimensions();
+    if width <= size && height <= size {
+        return Cow::Borrowed(image);
+    }
+    Cow::Owned(image.resize(size, size, image::imageops::FilterType::Lanczos3))
+}
+
+#[derive(FromForm, Debug)]
+pub struct PostFields<'r> {
+    pub title: Option<NonEmptyStr<'r>>,
+    pub author: Option<NonEmptyStr<'r>>,
+    pub email: Option<NonEmptyStr<'r>>,
+    pub sage: bool,
+    pub content: Option<NonEmptyStr<'r>>,
+    pub thread: Option<i32>,
+    pub board: NonEmptyStr<'r>,
+    /// Every file picked in the form's `image` input, in order.
+    #[field(name = "image")]
+    pub images: Vec<Option<Bytes>>,
+    pub captcha: Option<NonEmptyStr<'r>>,
+}
+
+impl<'r> PostFields<'r> {
+    pub fn captcha(&self) -> Option<&str> {
+        self.captcha.as_deref()
+    }
+}
+
+/// The post form's fields, plus the honeypot field named by `HONEYPOT_FIELD`.
+/// The name is only known at runtime, so that field is picked out before the rest are parsed.
+#[derive(Debug)]
+pub struct PostForm<'r> {
+    fields: PostFields<'r>,
+    /// Hidden from people, so anything in it came from a bot.
+    pub honeypot: Option<&'r str>,
+}
+
+impl<'r> Deref for PostForm<'r> {
+    type Target = PostFields<'r>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.fields
+    }
+}
+
+/// Whether `field` is the honeypot, which only ever holds text.
+fn is_honeypot(field: &rocket::form::name::NameView<'_>) -> bool {
+    field.key_lossy().as_str() == CONFIG.honeypot_field
+}
+
+#[async_trait]
+impl<'r> rocket::form::FromForm<'r> for PostForm<'r> {
+    type Context = (
+        <PostFields<'r> as rocket::form::FromForm<'r>>::Context,
+        Option<&'r str>,
+    );
+
+    fn init(opts: rocket::form::Options) -> Self::Context {
+        (
+            <PostFields<'r> as rocket::form::FromForm<'r>>::init(opts),
+            None,
+        )
+    }
+
+    fn push_value(ctxt: &mut Self::Context, field: rocket::form::ValueField<'r>) {
+        if is_honeypot(&field.name) {
+            ctxt.1 = Some(field.value).filter(|v| !v.is_empty());
+        } else {
+            <PostFields<'r> as rocket::form::FromForm<'r>>::push_value(&mut ctxt.0, field);
+        }
+    }
+
+    async fn push_data(ctxt: &mut Self::Context, field: rocket::form::DataField<'r, '_>) {
+        if is_honeypot(&field.name) {
+            // Bots filling in the field with a file are still bots.
+            ctxt.1 = Some("");
+        } else {
+            <PostFields<'r> as rocket::form::FromForm<'r>>::push_data(&mut ctxt.0, field).await;
+        }
+    }
+
+    fn push_error(ctxt: &mut Self::Context, error: rocket::form::Error<'r>) {
+        <PostFields<'r> as rocket::form::FromForm<'r>>::push_error(&mut ctxt.0, error);
+    }
+
+    fn finalize(ctxt: Self::Context) -> rocket::form::Result<'r, Self> {
+        Ok(Self {
+            fields: <PostFields<'r> as rocket::form::FromForm<'r>>::finalize(ctxt.0)?,
+            honeypot: ctxt.1,
+        })
+    }
+}
+
+#[derive(FromForm, Debug)]
+pub struct CaptchaForm<'r> {
+    pub captcha: NonEmptyStr<'r>,
+}
+
+#[derive(FromForm, Debug)]
+pub struct ReportForm<'r> {
+    pub board: NonEmptyStr<'r>,
+    pub post: i32,
+    pub reason: NonEmptyStr<'r>,
+}
+
+/// Deleting a board takes typing its name in again.
+#[derive(FromForm, Debug)]
+pub struct DeleteBoardForm<'r> {
+    pub confirm: &'r str,
+    pub purge_images: bool,
+}
+
+#[derive(FromForm, Debug)]
+pub struct CssForm<'r> {
+    pub css: Option<NonEmptyStr<'r>>,
+}
+
+#[derive(FromForm, Debug)]
+pub struct BoardForm<'r> {
+    pub name: NonEmptyStr<'r>,
+    pub title: NonEmptyStr<'r>,
+    pub description: Option<NonEmptyStr<'r>>,
+    pub require_content_for_op: bool,
+}
+
+#[derive(FromForm, Debug)]
+pub struct BoardEditForm<'r> {
+    pub title: NonEmptyStr<'r>,
+    pub description: Option<NonEmptyStr<'r>>,
+}
+
+#[derive(Debug)]
+/// An uploaded file, cut off at `MAX_UPLOAD_BYTES`.
+pub struct Bytes {
+    buf: Vec<u8>,
+    content_type: Option<ContentType>,
+    complete: bool,
+}
+
+impl Bytes {
+    /// The content type the client claimed for the upload, if any.
+    pub fn content_type(&self) -> Option<&ContentType> {
+        self.content_type.as_ref()
+    }
+
+    /// Whether the upload fit under `MAX_UPLOAD_BYTES`, if not only the start of it was read.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        self.buf.deref()
+    }
+}
+
+#[async_trait]
+impl<'v> FromFormField<'v> for Bytes {
+    async fn from_data(field: rocket::form::DataField<'v, '_>) -> rocket::form::Result<'v, Self> {
+        let content_type = field.content_type.clone();
+        let stream = field.data.open(CONFIG.max_upload_bytes.bytes());
+        let buf = stream
+            .into_bytes()
+            .await
+            .map_err(|e| rocket::form::Errors::from(rocket::form::Error::custom(e)))?;
+        if buf.is_empty() {
+            Err(rocket::form::Error::validation("Empty files are not allowed").into())
+        } else {
+            Ok(Self {
+                complete: buf.is_complete(),
+                buf: buf.into_inner(),
+                content_type: Some(content_type),
+            })
+        }
+    }
+
+    fn from_value(field: rocket::form::ValueField<'v>) -> rocket::form::Result<'v, Self> {
+        Ok(Self {
+            buf: field.value.as_bytes().to_owned(),
+            content_type: None,
+            complete: true,
+        })
+    }
+
+    fn default() -> Option<Self> {
+        None
+    }
+}
+
+/// Hash a poster's token as it's stored with their posts, so that the database alone
+/// can't be used to pose as them.
+pub fn poster_hash(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+/// The form `ip` is kept in the database in. With `IP_HASH_SALT` set that's a keyed hash
+/// dressed up as a unique local IPv6 address, so the same client always maps to the same value
+/// without the real address ever being written down. Range bans can't match hashed addresses.
+pub fn stored_ip(ip: IpAddr) -> IpNetwork {
+    match &CONFIG.ip_hash_salt {
+        Some(salt) => hashed_ip(ip, salt),
+        None => ip.into(),
+    }
+}
+
+/// HMAC-SHA256 of `ip` keyed with `salt`, cut down to an IPv6 address in `fd00::/8`.
+/// Without the salt, there's no telling which of the few billion IPv4 addresses it came from.
+fn hashed_ip(ip: IpAddr, salt: &str) -> IpNetwork {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(salt.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(ip.to_string().as_bytes());
+    let mut hash = [0; 16];
+    hash.copy_from_slice(&mac.finalize().into_bytes()[..16]);
+    hash[0] = 0xfd;
+    IpAddr::V6(Ipv6Addr::from(hash)).into()
+}
+
+/// An `ILIKE` pattern matching text that contains `text` anywhere,
+/// with `text`'s own wildcards escaped so they only match themselves.
+fn like_pattern(text: &str) -> String {
+    let mut pattern = String::with_capacity(text.len() + 2);
+    pattern.push('%');
+    for c in text.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+/// Turn free text into a `tsquery` matching posts with all of its words,
+/// or an empty string if there are none.
+fn search_terms(query: &str) -> String {
+    query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("{}:*", word.to_lowercase()))
+        .collect::<Vec<_>>()
+        .join(" & ")
+}
+
+pub struct Ban {
+    ip: IpNetwork,
+    reason: String,
+    created_at: PrimitiveDateTime,
+    expires_at: PrimitiveDateTime,
+}
+
+impl Ban {
+    /// Ban `ip`, which may be a whole range, from posting for `duration`.
+    pub async fn create(
+        ip: IpNetwork,
+        reason: &str,
+        duration: Duration,
+        pool: &PgPool,
+    ) -> Result<(), sqlx::Error> {
+        query!(
+            "INSERT INTO bans(ip, reason, created_at, duration)
+            VALUES ($1, $2, NOW(), make_interval(secs => $3))",
+            ip,
+            reason,
+            duration.as_secs_f64()
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Get every ban that hasn't run out yet, newest first.
+    pub async fn list(pool: &PgPool) -> Result<Vec<Ban>, sqlx::Error> {
+        query_as!(
+            Ban,
+            r#"SELECT ip, reason, created_at, created_at + duration AS "expires_at!"
+            FROM bans
+            WHERE created_at + duration > NOW()
+            ORDER BY created_at DESC"#
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Lift every ban on exactly `ip`.
+    pub async fn remove(ip: IpNetwork, pool: &PgPool) -> Result<(), Error> {
+        let removed = query!("DELETE FROM bans WHERE ip = $1", ip)
+            .execute(pool)
+            .await?
+            .rows_affected();
+        if removed == 0 {
+            return Err(Error::NotFound);
+        }
+        Ok(())
+    }
+
+    pub fn ip(&self) -> IpNetwork {
+        self.ip
+    }
+
+    pub fn reason(&self) -> &str {
+        self.reason.as_ref()
+    }
+
+    pub fn created_at(&self) -> PrimitiveDateTime {
+        self.created_at
+    }
+
+    pub fn expires_at(&self) -> PrimitiveDateTime {
+        self.expires_at
+    }
+}
+
+/// A ban length like `30m`, `12h`, `7d` or `2w`, or `perm` for one that never runs out.
+#[derive(Debug)]
+pub struct BanDuration(pub Duration);
+
+impl<'v> FromFormField<'v> for BanDuration {
+    fn from_value(field: rocket::form::ValueField<'v>) -> rocket::form::Result<'v, Self> {
+        let value = field.value.trim();
+        if value == "perm" {
+            return Ok(Self(Duration::from_secs(100 * 365 * 24 * 60 * 60)));
+        }
+        let split = value.len() - value.chars().last().map_or(0, char::len_utf8);
+        let unit = match &value[split..] {
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "w" => 7 * 24 * 60 * 60,
+            _ => return Err(rocket::form::Error::validation("Unknown duration unit").into()),
+        };
+        let count: u64 = value[..split]
+            .parse()
+            .map_err(|_| rocket::form::Error::validation("Invalid duration"))?;
+        Ok(Self(Duration::from_secs(count.saturating_mul(unit))))
+    }
+}
+
+/// Bans either name the IP or range to ban, or a post whose poster to ban.
+#[derive(FromForm, Debug)]
+pub struct BanForm<'r> {
+    pub ip: Option<NonEmptyStr<'r>>,
+    pub board: Option<NonEmptyStr<'r>>,
+    pub post: Option<i32>,
+    pub reason: NonEmptyStr<'r>,
+    pub duration: BanDuration,
+}
+
+#[derive(Debug)]
+pub struct NotBanned;
+
+#[async_trait]
 impl<'r> FromRequest<'r> for NotBanned {
     type Error = Error;
 
     async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
         let pool = request.rocket().state::<PgPool>().unwrap();
-        let ip: IpNetwork = request.client_ip().unwrap().into();
+        let ip = stored_ip(request.client_ip().unwrap());
         let ban = match query!(
             "SELECT reason
             FROM bans
@@ -633,42 +2938,453 @@ impl Captcha {
         })
     }
 
+    /// Check `answer` and use the captcha up, right or wrong.
+    /// Captchas older than `CAPTCHA_TTL_SECS` count as wrong even before they're pruned.
     pub async fn verify(id: Uuid, answer: &str, pool: &PgPool) -> Result<bool, Error> {
         let captcha = query!(
-            "DELETE FROM captchas
+            r#"DELETE FROM captchas
             WHERE id = $1
-            RETURNING solution",
-            id
+            RETURNING solution, created_at + make_interval(secs => $2) > NOW() AS "fresh!""#,
+            id,
+            CONFIG.captcha_ttl.as_secs_f64()
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        if let Some(captcha) = captcha {
+            Ok(captcha.fresh && captcha.solution == answer.to_lowercase())
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Check an answer like `verify`, but leave the captcha in place for the actual post.
+    pub async fn peek(id: Uuid, answer: &str, pool: &PgPool) -> Result<bool, Error> {
+        let captcha = query!(
+            "SELECT solution FROM captchas
+            WHERE id = $1 AND created_at + make_interval(secs => $2) > NOW()",
+            id,
+            CONFIG.captcha_ttl.as_secs_f64()
         )
         .fetch_optional(pool)
         .await?;
 
-        if let Some(captcha) = captcha {
-            Ok(captcha.solution == answer.to_lowercase())
-        } else {
-            Ok(false)
+        Ok(captcha.map_or(false, |c| c.solution == answer.to_lowercase()))
+    }
+
+    /// Delete captchas older than `ttl`, returning how many there were.
+    pub async fn prune(ttl: Duration, pool: &PgPool) -> Result<u64, sqlx::Error> {
+        Ok(query!(
+            "DELETE FROM captchas WHERE created_at + make_interval(secs => $1) < NOW()",
+            ttl.as_secs_f64()
+        )
+        .execute(pool)
+        .await?
+        .rows_affected())
+    }
+
+    /// Fail with `Error::CaptchaLockout` if `ip` got too many captchas wrong recently.
+    pub async fn ensure_not_locked_out(ip: IpNetwork, pool: &PgPool) -> Result<(), Error> {
+        let locked = query!(
+            r#"SELECT EXISTS (
+                SELECT ip FROM captcha_failures
+                WHERE ip = $1 AND failures >= $2 AND last_failure + make_interval(secs => $3) > NOW()
+            ) AS "locked!""#,
+            ip,
+            CONFIG.captcha_max_failures,
+            CONFIG.captcha_lockout.as_secs_f64()
+        )
+        .fetch_one(pool)
+        .await?
+        .locked;
+        if locked {
+            Err(Error::CaptchaLockout)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Count a wrong answer from `ip`. Failures older than the lockout period are forgotten.
+    pub async fn record_failure(ip: IpNetwork, pool: &PgPool) -> Result<(), sqlx::Error> {
+        query!(
+            "INSERT INTO captcha_failures(ip, failures, last_failure)
+            VALUES ($1, 1, NOW())
+            ON CONFLICT (ip) DO UPDATE
+                SET failures = CASE
+                        WHEN captcha_failures.last_failure + make_interval(secs => $2) > NOW()
+                        THEN captcha_failures.failures + 1
+                        ELSE 1
+                    END,
+                    last_failure = NOW()",
+            ip,
+            CONFIG.captcha_lockout.as_secs_f64()
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn clear_failures(ip: IpNetwork, pool: &PgPool) -> Result<(), sqlx::Error> {
+        query!("DELETE FROM captcha_failures WHERE ip = $1", ip)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub fn base64image(&self) -> &str {
+        self.base64image.as_ref()
+    }
+
+    pub fn solution(&self) -> &str {
+        self.solution.as_ref()
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+}
+
+/// How the catalog orders its threads.
+#[derive(rocket::FromFormField, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CatalogSort {
+    /// The board page's order: stickies first, then by last bump.
+    #[default]
+    Bump,
+    /// Newest threads first.
+    Created,
+    /// Most replied to threads first.
+    Replies,
+}
+
+impl CatalogSort {
+    pub const ALL: [CatalogSort; 3] = [
+        CatalogSort::Bump,
+        CatalogSort::Created,
+        CatalogSort::Replies,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CatalogSort::Bump => "bump",
+            CatalogSort::Created => "created",
+            CatalogSort::Replies => "replies",
+        }
+    }
+}
+
+impl UriDisplay<Query> for CatalogSort {
+    fn fmt(&self, f: &mut Formatter<'_, Query>) -> std::fmt::Result {
+        f.write_value(self.as_str())
+    }
+}
+
+impl_from_uri_param_identity!([Query] CatalogSort);
+
+/// Unit of time that posting statistics are grouped by.
+#[derive(rocket::FromFormField, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsBucket {
+    Hour,
+    Day,
+}
+
+impl StatsBucket {
+    fn as_str(self) -> &'static str {
+        match self {
+            StatsBucket::Hour => "hour",
+            StatsBucket::Day => "day",
+        }
+    }
+}
+
+/// Site-wide totals for the admin dashboard.
+pub struct Stats {
+    boards: i64,
+    threads: i64,
+    posts: i64,
+    posts_today: i64,
+    active_bans: i64,
+    open_reports: i64,
+}
+
+impl Stats {
+    pub async fn gather(pool: &PgPool) -> Result<Stats, sqlx::Error> {
+        query_as!(
+            Stats,
+            r#"SELECT
+                (SELECT COUNT(*) FROM boards) AS "boards!",
+                (SELECT COUNT(*) FROM posts WHERE id = thread AND deleted_at IS NULL) AS "threads!",
+                (SELECT COUNT(*) FROM posts WHERE deleted_at IS NULL) AS "posts!",
+                (SELECT COUNT(*) FROM posts
+                    WHERE deleted_at IS NULL AND posted_at >= date_trunc('day', NOW())) AS "posts_today!",
+                (SELECT COUNT(*) FROM bans WHERE created_at + duration > NOW()) AS "active_bans!",
+                (SELECT COUNT(*) FROM reports WHERE NOT dismissed) AS "open_reports!""#
+        )
+        .fetch_one(pool)
+        .await
+    }
+
+    pub fn boards(&self) -> i64 {
+        self.boards
+    }
+
+    pub fn threads(&self) -> i64 {
+        self.threads
+    }
+
+    pub fn posts(&self) -> i64 {
+        self.posts
+    }
+
+    pub fn posts_today(&self) -> i64 {
+        self.posts_today
+    }
+
+    pub fn active_bans(&self) -> i64 {
+        self.active_bans
+    }
+
+    pub fn open_reports(&self) -> i64 {
+        self.open_reports
+    }
+}
+
+/// Number of posts made within one bucket of time.
+pub struct PostCount {
+    bucket: PrimitiveDateTime,
+    posts: i64,
+}
+
+impl PostCount {
+    /// Count posts per `bucket` over the last `days`, on `board` or on every board.
+    pub async fn series(
+        bucket: StatsBucket,
+        board: Option<&str>,
+        days: i32,
+        pool: &PgPool,
+    ) -> Result<Vec<PostCount>, sqlx::Error> {
+        query_as!(
+            PostCount,
+            r#"SELECT date_trunc($1, posted_at) AS "bucket!", COUNT(*) AS "posts!"
+            FROM posts
+            WHERE ($2::VARCHAR IS NULL OR board = $2)
+                AND posted_at > NOW() - make_interval(days => $3)
+            GROUP BY 1
+            ORDER BY 1"#,
+            bucket.as_str(),
+            board,
+            days
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub fn bucket(&self) -> &PrimitiveDateTime {
+        &self.bucket
+    }
+
+    pub fn posts(&self) -> i64 {
+        self.posts
+    }
+}
+
+pub struct Report {
+    id: i32,
+    board: String,
+    post_id: i32,
+    reason: String,
+    count: i32,
+    created_at: PrimitiveDateTime,
+}
+
+impl Report {
+    /// Flag a post for the mods. Reporting the same post again from the same IP
+    /// bumps the existing report's count instead, and reopens it if it was dismissed.
+    pub async fn create(
+        board: &str,
+        post_id: i32,
+        reason: &str,
+        ip: IpNetwork,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        if reason.chars().count() > REPORT_REASON_MAX_CHARS {
+            return Err(Error::ReportReasonTooLong);
+        }
+        query!(
+            "INSERT INTO reports(board, post_id, reason, ip)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (board, post_id, ip) DO UPDATE
+                SET count = reports.count + 1, reason = EXCLUDED.reason, dismissed = FALSE",
+            board,
+            post_id,
+            reason,
+            ip
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Get every report that hasn't been dismissed, most reported first.
+    pub async fn open(pool: &PgPool) -> Result<Vec<Report>, sqlx::Error> {
+        query_as!(
+            Report,
+            "SELECT id, board, post_id, reason, count, created_at
+            FROM reports
+            WHERE NOT dismissed
+            ORDER BY count DESC, created_at"
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn dismiss(id: i32, pool: &PgPool) -> Result<(), Error> {
+        let dismissed = query!("UPDATE reports SET dismissed = TRUE WHERE id = $1", id)
+            .execute(pool)
+            .await?
+            .rows_affected();
+        if dismissed == 0 {
+            return Err(Error::NotFound);
+        }
+        Ok(())
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn board(&self) -> &str {
+        self.board.as_ref()
+    }
+
+    pub fn post_id(&self) -> i32 {
+        self.post_id
+    }
+
+    pub fn reason(&self) -> &str {
+        self.reason.as_ref()
+    }
+
+    pub fn count(&self) -> i32 {
+        self.count
+    }
+
+    pub fn created_at(&self) -> &PrimitiveDateTime {
+        &self.created_at
+    }
+}
+
+/// The current or next occurrence of a recurring maintenance window,
+/// during which posting is disabled.
+pub struct MaintenanceWindow {
+    starts_at: PrimitiveDateTime,
+    ends_at: PrimitiveDateTime,
+    active: bool,
+}
+
+impl MaintenanceWindow {
+    pub async fn upcoming(pool: &PgPool) -> Result<Option<Self>, sqlx::Error> {
+        query_as!(
+            MaintenanceWindow,
+            r#"SELECT
+                occ.starts_at AS "starts_at!",
+                occ.starts_at + duration AS "ends_at!",
+                occ.starts_at <= LOCALTIMESTAMP AS "active!"
+            FROM maintenance_windows,
+                LATERAL (
+                    SELECT CURRENT_DATE + offs + starts_at AS starts_at
+                    FROM generate_series(-1, 7) AS offs
+                ) AS occ
+            WHERE (weekday IS NULL OR weekday = EXTRACT(DOW FROM occ.starts_at))
+                AND occ.starts_at + duration > LOCALTIMESTAMP
+            ORDER BY occ.starts_at
+            LIMIT 1"#
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Fail with `Error::ReadOnly` if a maintenance window is in progress.
+    pub async fn ensure_writable(pool: &PgPool) -> Result<(), Error> {
+        match MaintenanceWindow::upcoming(pool).await? {
+            Some(window) if window.active => Err(Error::ReadOnly(window.ends_at)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn starts_at(&self) -> &PrimitiveDateTime {
+        &self.starts_at
+    }
+
+    pub fn ends_at(&self) -> &PrimitiveDateTime {
+        &self.ends_at
+    }
+
+    pub fn active(&self) -> bool {
+        self.active
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrivelegeLevel {
+    Admin,
+    Mod,
+}
+
+impl PrivelegeLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Admin => "admin",
+            Self::Mod => "mod",
+        }
+    }
+
+    /// Whether users at this level can do what needs `needed`. Admins can do everything mods can.
+    pub fn allows(self, needed: PrivelegeLevel) -> bool {
+        match needed {
+            Self::Admin => self == Self::Admin,
+            Self::Mod => true,
+        }
+    }
+}
+
+impl std::str::FromStr for PrivelegeLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Self::Admin),
+            "mod" => Ok(Self::Mod),
+            _ => Err(format!(
+                "unknown privilege level `{s}`, the database enum has values this build doesn't know about"
+            )),
         }
     }
+}
 
-    pub fn base64image(&self) -> &str {
-        self.base64image.as_ref()
+// Implemented by hand rather than derived so that a value added to the database enum
+// fails with a readable message instead of a bare decode error, and so that databases
+// that haven't run the migration fixing the type's spelling still decode.
+impl sqlx::Type<Postgres> for PrivelegeLevel {
+    fn type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("privilege_level")
     }
 
-    pub fn solution(&self) -> &str {
-        self.solution.as_ref()
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        matches!(ty.name(), "privilege_level" | "privelege_level")
     }
+}
 
-    pub fn id(&self) -> Uuid {
-        self.id
+impl<'r> sqlx::Decode<'r, Postgres> for PrivelegeLevel {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        let level = <&str as sqlx::Decode<Postgres>>::decode(value)?;
+        Ok(level.parse()?)
     }
 }
 
-#[derive(sqlx::Type)]
-#[sqlx(type_name = "privelege_level")]
-#[sqlx(rename_all = "lowercase")]
-pub enum PrivelegeLevel {
-    Admin,
-    Mod,
+impl sqlx::Encode<'_, Postgres> for PrivelegeLevel {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> IsNull {
+        <&str as sqlx::Encode<Postgres>>::encode(self.as_str(), buf)
+    }
 }
 
 pub struct User {
@@ -678,16 +3394,27 @@ pub struct User {
 }
 
 impl User {
-    pub async fn new(name: &str, level: PrivelegeLevel, pool: &PgPool) -> Result<Self, Error> {
+    pub async fn new(
+        name: &str,
+        password: &str,
+        level: PrivelegeLevel,
+        pool: &PgPool,
+    ) -> Result<Self, Error> {
         let id = Uuid::from_bytes(uuid::Uuid::new_v4().into_bytes());
+        let salt = SaltString::generate(&mut OsRng);
+        let password_hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(Error::PasswordHash)?
+            .to_string();
         let user = query_as!(
             User,
-            r#"INSERT INTO users(id, name, level)
-            VALUES ($1, $2, $3)
+            r#"INSERT INTO users(id, name, level, password_hash)
+            VALUES ($1, $2, $3, $4)
             RETURNING id, name, level AS "level!: PrivelegeLevel""#,
             id,
             name,
-            level as PrivelegeLevel
+            level as PrivelegeLevel,
+            password_hash
         )
         .fetch_one(pool)
         .await?;
@@ -709,9 +3436,21 @@ impl Session {
         Ok(session)
     }
 
+    /// Log in as `name`, failing with `Error::InvalidCredentials` if there's no such user
+    /// or the password doesn't match.
     pub async fn new(name: &str, password: &str, pool: &PgPool) -> Result<Self, Error> {
+        let user = query!("SELECT id, password_hash FROM users WHERE name = $1", name)
+            .fetch_optional(pool)
+            .await?
+            .ok_or(Error::InvalidCredentials)?;
+        let password_hash = user.password_hash.ok_or(Error::InvalidCredentials)?;
+        let password_hash = PasswordHash::new(&password_hash).map_err(Error::PasswordHash)?;
+        Argon2::default()
+            .verify_password(password.as_bytes(), &password_hash)
+            .map_err(|_| Error::InvalidCredentials)?;
+
         let id = Uuid::from_bytes(uuid::Uuid::new_v4().into_bytes());
-        let uid: Uuid = Uuid::from_bytes(uuid::Uuid::new_v4().into_bytes());
+        let uid = user.id;
         let session = query_as!(
             Session,
             "INSERT INTO sessions (id, uid) VALUES ($1, $2) RETURNING *",
@@ -723,11 +3462,48 @@ impl Session {
         Ok(session)
     }
 
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
     pub fn uid(&self) -> Uuid {
         self.uid
     }
+
+    /// Delete sessions that logged in more than `ttl` ago, returning how many there were.
+    pub async fn prune(ttl: Duration, pool: &PgPool) -> Result<u64, sqlx::Error> {
+        Ok(query!(
+            "DELETE FROM sessions WHERE logged_in_at + make_interval(secs => $1) < NOW()",
+            ttl.as_secs_f64()
+        )
+        .execute(pool)
+        .await?
+        .rows_affected())
+    }
+}
+
+/// The logged in user behind the request's session cookie, if there is one.
+async fn session_user(request: &Request<'_>) -> Option<(Uuid, PrivelegeLevel)> {
+    let pool = request.rocket().state::<PgPool>().unwrap();
+    let session: Uuid = request
+        .cookies()
+        .get_private("sessionid")?
+        .value()
+        .parse()
+        .ok()?;
+    let user = query!(
+        r#"SELECT users.id, users.level AS "level!: PrivelegeLevel"
+        FROM sessions JOIN users ON users.id = sessions.uid
+        WHERE sessions.id = $1"#,
+        session
+    )
+    .fetch_optional(pool)
+    .await
+    .ok()??;
+    Some((user.id, user.level))
 }
 
+/// Passes for admins only.
 pub struct AdminPrivilege {
     uid: Uuid,
 }
@@ -743,20 +3519,915 @@ impl<'r> FromRequest<'r> for AdminPrivilege {
     type Error = Error;
 
     async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
-        let pool = request.rocket().state::<PgPool>().unwrap();
-        let session = request.cookies().get_private("sessionid");
-        let session = session.map(|c| c.value().parse());
-        if let Some(Ok(session)) = session {
-            if let Ok(Some(session)) = Session::get(session, pool).await {
-                return request::Outcome::Success(Self { uid: session.uid() });
+        match session_user(request).await {
+            Some((uid, level)) if level.allows(PrivelegeLevel::Admin) => {
+                request::Outcome::Success(Self { uid })
+            }
+            _ => request::Outcome::Forward(()),
+        }
+    }
+}
+
+/// Passes for mods and admins.
+pub struct ModPrivilege {
+    uid: Uuid,
+    level: PrivelegeLevel,
+}
+
+impl ModPrivilege {
+    pub fn uid(&self) -> Uuid {
+        self.uid
+    }
+
+    pub fn level(&self) -> PrivelegeLevel {
+        self.level
+    }
+}
+
+#[async_trait]
+impl<'r> FromRequest<'r> for ModPrivilege {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match session_user(request).await {
+            Some((uid, level)) if level.allows(PrivelegeLevel::Mod) => {
+                request::Outcome::Success(Self { uid, level })
             }
+            _ => request::Outcome::Forward(()),
         }
-        request::Outcome::Forward(())
     }
 }
 
 #[derive(FromForm)]
 pub struct LoginForm<'r> {
-    name: NonEmptyStr<'r>,
-    password: NonEmptyStr<'r>,
+    pub name: NonEmptyStr<'r>,
+    pub password: NonEmptyStr<'r>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn like_pattern_matches_anywhere() {
+        assert_eq!(like_pattern("cats"), "%cats%");
+    }
+
+    #[test]
+    fn like_pattern_escapes_wildcards() {
+        assert_eq!(like_pattern("100%_off"), "%100\\%\\_off%");
+        assert_eq!(like_pattern("a\\b"), "%a\\\\b%");
+    }
+
+    #[test]
+    fn tripcodes_match_known_vectors() {
+        assert_eq!(tripcode("password"), "ozOtJW9BFA");
+        assert_eq!(tripcode("tea"), "WokonZwxw2");
+        // Too short for a salt of its own, so it's padded with "H.".
+        assert_eq!(tripcode("a"), "ZnBI2EKkq.");
+        // Secrets are HTML escaped before hashing, like on the boards the algorithm comes from.
+        assert_eq!(tripcode("<3"), "0JTVzlbXog");
+    }
+
+    #[test]
+    fn author_splits_off_secret() {
+        let (name, trip) = parse_author(Some("anon#password")).unwrap();
+        assert_eq!(name, Some("anon"));
+        assert_eq!(trip.as_deref(), Some("ozOtJW9BFA"));
+    }
+
+    #[test]
+    fn author_without_secret_has_no_tripcode() {
+        let (name, trip) = parse_author(Some("anon")).unwrap();
+        assert_eq!(name, Some("anon"));
+        assert_eq!(trip, None);
+        assert!(matches!(parse_author(None), Ok((None, None))));
+    }
+
+    #[test]
+    fn secret_alone_posts_anonymously_with_tripcode() {
+        let (name, trip) = parse_author(Some("#password")).unwrap();
+        assert_eq!(name, None);
+        assert_eq!(trip.as_deref(), Some("ozOtJW9BFA"));
+    }
+
+    #[test]
+    fn empty_secret_is_ignored() {
+        let (name, trip) = parse_author(Some("anon#")).unwrap();
+        assert_eq!(name, Some("anon"));
+        assert_eq!(trip, None);
+        assert!(matches!(parse_author(Some("#")), Ok((None, None))));
+    }
+
+    #[test]
+    fn double_hash_is_part_of_the_secret() {
+        // There are no secure tripcodes, so only the first `#` splits and the rest is the secret.
+        let (name, trip) = parse_author(Some("anon##pw")).unwrap();
+        assert_eq!(name, Some("anon"));
+        assert_eq!(trip.as_deref(), Some("pjvwHVG5yE"));
+        assert_eq!(trip, Some(tripcode("#pw")));
+    }
+
+    #[test]
+    fn overlong_name_is_rejected() {
+        let name = "a".repeat(CONFIG.author_max_chars + 1);
+        assert!(matches!(
+            parse_author(Some(&name)),
+            Err(Error::AuthorTooLong)
+        ));
+        let name = "a".repeat(CONFIG.author_max_chars);
+        assert!(parse_author(Some(&format!("{name}#secret"))).is_ok());
+    }
+
+    #[test]
+    fn css_cant_close_its_style_element() {
+        let css = "body { color: red; }</style><script>alert(1)</script>";
+        assert!(matches!(check_css(css), Err(Error::InvalidCss(_))));
+    }
+
+    #[test]
+    fn css_imports_are_refused() {
+        let css = "@import url(https://example.com/evil.css);";
+        assert!(matches!(check_css(css), Err(Error::InvalidCss(_))));
+        assert!(matches!(
+            check_css("@IMPORT 'x.css';"),
+            Err(Error::InvalidCss(_))
+        ));
+    }
+
+    #[test]
+    fn escaped_css_imports_are_refused() {
+        assert!(matches!(
+            check_css("@\\69mport 'x.css';"),
+            Err(Error::InvalidCss(_))
+        ));
+        assert!(matches!(
+            check_css("@\\000069 mport 'x.css';"),
+            Err(Error::InvalidCss(_))
+        ));
+        assert!(matches!(
+            check_css("@i\\mport 'x.css';"),
+            Err(Error::InvalidCss(_))
+        ));
+    }
+
+    #[test]
+    fn css_size_is_capped() {
+        let rule = "a{}";
+        let fits = rule.repeat(CONFIG.custom_css_max_bytes / rule.len());
+        assert!(check_css(&fits).is_ok());
+        let too_long = "a".repeat(CONFIG.custom_css_max_bytes + 1);
+        assert!(matches!(check_css(&too_long), Err(Error::InvalidCss(_))));
+    }
+
+    #[test]
+    fn plain_css_is_accepted() {
+        assert!(check_css("body { background: #fff; } .post > .info { color: blue; }").is_ok());
+    }
+
+    /// Never connects, rendering only touches the database for `LINKIFY_BOARDS`.
+    fn offline_pool() -> PgPool {
+        PgPool::connect_lazy("postgres://localhost/ruburu").unwrap()
+    }
+
+    async fn render(body: &str, replied: &[(i32, i32)]) -> String {
+        let segments = render_segments(body, &offline_pool()).await.unwrap();
+        link_replies(&segments, "b", replied)
+    }
+
+    #[rocket::async_test]
+    async fn spoilers_are_wrapped() {
+        assert_eq!(
+            render("[spoiler]secret[/spoiler]", &[]).await,
+            r#"<span class="spoiler">secret</span><br>"#
+        );
+        assert_eq!(
+            render("[spoiler]two\nlines[/spoiler]", &[]).await,
+            r#"<span class="spoiler">two<br>lines</span><br>"#
+        );
+    }
+
+    #[rocket::async_test]
+    async fn spoilers_keep_inline_markup() {
+        assert_eq!(
+            render("[spoiler]**x**[/spoiler]", &[]).await,
+            r#"<span class="spoiler"><b>x</b></span><br>"#
+        );
+    }
+
+    #[rocket::async_test]
+    async fn unterminated_spoiler_stays_text() {
+        assert_eq!(render("[spoiler]secret", &[]).await, "[spoiler]secret<br>");
+    }
+
+    #[rocket::async_test]
+    async fn nested_spoilers_close_at_the_first_end_tag() {
+        let html = render("[spoiler]a [spoiler]b[/spoiler] c[/spoiler]", &[]).await;
+        assert_eq!(
+            html,
+            r#"<span class="spoiler">a [spoiler]b</span> c[/spoiler]<br>"#
+        );
+        assert_eq!(
+            html.matches("<span").count(),
+            html.matches("</span>").count()
+        );
+    }
+
+    #[rocket::async_test]
+    async fn spoilers_keep_reply_links() {
+        assert_eq!(
+            render("[spoiler]>>5[/spoiler]", &[(5, 1)]).await,
+            r#"<span class="spoiler"><a href="/b/1#5">&gt;&gt;5</a></span><br>"#
+        );
+    }
+
+    #[rocket::async_test]
+    async fn spoilers_dont_span_greentext() {
+        let html = render("[spoiler]a\n>green\nb[/spoiler]", &[]).await;
+        assert!(!html.contains("<span"));
+        assert!(html.contains(r#"<div class="green-text">&gt;green</div>"#));
+    }
+
+    #[rocket::async_test]
+    async fn board_descriptions_get_post_markup() {
+        let html = render_description("**Rules**\n>be nice", &offline_pool())
+            .await
+            .unwrap();
+        assert_eq!(
+            html,
+            r#"<b>Rules</b><br><div class="green-text">&gt;be nice</div><br>"#
+        );
+    }
+
+    #[rocket::async_test]
+    async fn board_descriptions_are_escaped() {
+        let html = render_description("<script>alert(1)</script>", &offline_pool())
+            .await
+            .unwrap();
+        assert_eq!(html, "&lt;script&gt;alert(1)&lt;/script&gt;<br>");
+    }
+
+    #[rocket::async_test]
+    async fn fenced_code_is_left_alone() {
+        let body = "```\n>green **bold** >>5 /b/\n```";
+        let segments = render_segments(body, &offline_pool()).await.unwrap();
+        assert!(referenced_posts(&segments).is_empty());
+        let html = link_replies(&segments, "b", &[(5, 1)]);
+        assert_eq!(
+            html,
+            "<pre><code>&gt;green **bold** &gt;&gt;5 /b/</code></pre>"
+        );
+    }
+
+    #[rocket::async_test]
+    async fn fenced_html_is_escaped() {
+        let html = render("```\n<script>alert(1)</script>\n```", &[]).await;
+        assert_eq!(
+            html,
+            "<pre><code>&lt;script&gt;alert(1)&lt;/script&gt;</code></pre>"
+        );
+    }
+
+    #[rocket::async_test]
+    async fn text_around_fences_is_formatted() {
+        let body = ">>5\n```\n>>5\n```\n>green";
+        let segments = render_segments(body, &offline_pool()).await.unwrap();
+        assert_eq!(referenced_posts(&segments), vec![5]);
+        let html = link_replies(&segments, "b", &[(5, 1)]);
+        assert_eq!(
+            html,
+            concat!(
+                r#"<a href="/b/1#5">&gt;&gt;5</a><br>"#,
+                "<pre><code>&gt;&gt;5</code></pre>",
+                r#"<div class="green-text">&gt;green</div><br>"#
+            )
+        );
+    }
+
+    #[rocket::async_test]
+    async fn unterminated_fence_stays_text() {
+        assert_eq!(render("```\n>>5", &[]).await, "```<br>&gt;&gt;5<br>");
+    }
+
+    #[test]
+    fn board_names_are_lowercased_and_trimmed() {
+        assert_eq!(board_name("Tech").unwrap(), "tech");
+        assert_eq!(board_name("  v2 ").unwrap(), "v2");
+    }
+
+    #[test]
+    fn reserved_board_names_are_refused() {
+        for name in ["admin", "static", "api", "Admin", "API"] {
+            assert!(
+                matches!(board_name(name), Err(Error::InvalidBoardName)),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn board_name_length_is_bounded() {
+        assert_eq!(board_name("a").unwrap(), "a");
+        assert_eq!(board_name(&"a".repeat(16)).unwrap(), "a".repeat(16));
+        assert!(matches!(board_name(""), Err(Error::InvalidBoardName)));
+        assert!(matches!(board_name("   "), Err(Error::InvalidBoardName)));
+        assert!(matches!(
+            board_name(&"a".repeat(17)),
+            Err(Error::InvalidBoardName)
+        ));
+    }
+
+    #[test]
+    fn board_names_are_letters_and_digits() {
+        for name in ["a/b", "a b", "a-b", "a_b", "ä", "../x"] {
+            assert!(
+                matches!(board_name(name), Err(Error::InvalidBoardName)),
+                "{name}"
+            );
+        }
+    }
+
+    fn strip(text: &str) -> Option<String> {
+        clean_text_with(Some(text), InvisibleChars::Strip)
+            .unwrap()
+            .map(Cow::into_owned)
+    }
+
+    #[test]
+    fn zero_width_spaces_are_stripped() {
+        assert_eq!(strip("ad\u{200B}min").as_deref(), Some("admin"));
+        assert_eq!(strip("\u{FEFF}hi\u{00AD}").as_deref(), Some("hi"));
+    }
+
+    #[test]
+    fn bidi_overrides_are_stripped() {
+        assert_eq!(strip("abc\u{202E}fed\u{202C}").as_deref(), Some("abcfed"));
+        assert_eq!(strip("\u{2066}x\u{2069}").as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn zwj_emoji_sequences_survive() {
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        assert_eq!(strip(family).as_deref(), Some(family));
+        assert!(matches!(
+            clean_text_with(Some(family), InvisibleChars::Reject),
+            Ok(Some(Cow::Borrowed(_)))
+        ));
+    }
+
+    #[test]
+    fn zwnj_in_persian_words_survives() {
+        // "I want", whose prefix is kept from joining onto the verb by a ZWNJ.
+        let word = "\u{0645}\u{06CC}\u{200C}\u{062E}\u{0648}\u{0627}\u{0647}\u{0645}";
+        assert_eq!(strip(word).as_deref(), Some(word));
+        assert!(matches!(
+            clean_text_with(Some(word), InvisibleChars::Reject),
+            Ok(Some(Cow::Borrowed(_)))
+        ));
+    }
+
+    #[test]
+    fn only_invisible_text_becomes_empty() {
+        assert_eq!(strip("\u{200B}\u{200B} "), None);
+    }
+
+    #[test]
+    fn newlines_and_tabs_are_kept() {
+        assert_eq!(strip("a\n\tb\u{0007}").as_deref(), Some("a\n\tb"));
+    }
+
+    #[test]
+    fn invisible_characters_can_be_rejected_or_allowed() {
+        let text = Some("a\u{200B}b");
+        assert!(matches!(
+            clean_text_with(text, InvisibleChars::Reject),
+            Err(Error::InvisibleCharacters)
+        ));
+        assert!(matches!(
+            clean_text_with(text, InvisibleChars::Allow),
+            Ok(Some(Cow::Borrowed("a\u{200B}b")))
+        ));
+    }
+
+    #[test]
+    fn field_at_limit_is_accepted() {
+        assert!(check_length("Subjects", Some(&"a".repeat(10)), 10).is_ok());
+        // Limits count characters, not bytes.
+        assert!(check_length("Subjects", Some(&"é".repeat(10)), 10).is_ok());
+        assert!(check_length("Subjects", None, 0).is_ok());
+    }
+
+    #[test]
+    fn overlong_field_is_rejected_by_name() {
+        let error = check_length("Subjects", Some(&"a".repeat(11)), 10).unwrap_err();
+        assert!(matches!(error, Error::TooLong("Subjects", 10)));
+        assert_eq!(
+            error.to_string(),
+            "Subjects can be at most 10 characters long"
+        );
+    }
+
+    #[test]
+    fn post_fields_use_their_own_limits() {
+        let subject = "a".repeat(CONFIG.subject_max_chars);
+        let content = "a".repeat(CONFIG.content_max_chars);
+        assert!(check_post_lengths(Some(&subject), None, Some(&content)).is_ok());
+        let email = "a".repeat(CONFIG.email_max_chars + 1);
+        assert!(matches!(
+            check_post_lengths(Some(&subject), Some(&email), None),
+            Err(Error::TooLong("Emails", _))
+        ));
+        let content = "a".repeat(CONFIG.content_max_chars + 1);
+        assert!(matches!(
+            check_post_lengths(None, None, Some(&content)),
+            Err(Error::TooLong("Posts", _))
+        ));
+    }
+
+    #[test]
+    fn board_fields_use_their_own_limits() {
+        let title = "a".repeat(CONFIG.board_title_max_chars);
+        assert!(check_board_lengths(&title, None).is_ok());
+        let title = "a".repeat(CONFIG.board_title_max_chars + 1);
+        assert!(matches!(
+            check_board_lengths(&title, None),
+            Err(Error::TooLong("Board titles", _))
+        ));
+        let description = "a".repeat(CONFIG.board_description_max_chars + 1);
+        assert!(matches!(
+            check_board_lengths("Random", Some(&description)),
+            Err(Error::TooLong("Board descriptions", _))
+        ));
+    }
+
+    fn thumb_size(width: u32, height: u32, fit: ThumbFit) -> (u32, u32) {
+        let image = DynamicImage::new_rgb8(width, height);
+        thumbnail_with(&image, fit, 4.0, 200).dimensions()
+    }
+
+    #[test]
+    fn thumbnails_keep_aspect_ratio() {
+        assert_eq!(thumb_size(400, 200, ThumbFit::Crop), (200, 100));
+        assert_eq!(thumb_size(300, 600, ThumbFit::Crop), (100, 200));
+        assert_eq!(thumb_size(1000, 1000, ThumbFit::Pad), (200, 200));
+    }
+
+    #[test]
+    fn small_images_are_not_resized() {
+        let image = DynamicImage::new_rgb8(120, 80);
+        let thumb = thumbnail_with(&image, ThumbFit::Crop, 4.0, 200);
+        assert!(matches!(thumb, Cow::Borrowed(_)));
+        assert_eq!(thumb.dimensions(), (120, 80));
+        assert!(matches!(shrink_to(&image, 120), Cow::Borrowed(_)));
+        assert!(matches!(shrink_to(&image, 119), Cow::Owned(_)));
+    }
+
+    #[test]
+    fn extreme_aspect_is_cropped() {
+        assert_eq!(thumb_size(1000, 100, ThumbFit::Crop), (200, 50));
+        assert_eq!(thumb_size(100, 1000, ThumbFit::Crop), (50, 200));
+        assert_eq!(thumb_size(5000, 10, ThumbFit::Crop), (40, 10));
+    }
+
+    #[test]
+    fn extreme_aspect_is_padded() {
+        assert_eq!(thumb_size(1000, 100, ThumbFit::Pad), (200, 50));
+        assert_eq!(thumb_size(100, 1000, ThumbFit::Pad), (50, 200));
+        assert_eq!(thumb_size(400, 200, ThumbFit::Pad), (200, 100));
+    }
+
+    #[test]
+    fn extreme_aspect_can_be_left_alone() {
+        assert_eq!(thumb_size(1000, 100, ThumbFit::None), (200, 20));
+    }
+
+    fn test_image() -> DynamicImage {
+        DynamicImage::ImageRgb8(image::RgbImage::from_fn(16, 16, |x, y| {
+            image::Rgb([(x * 16) as u8, (y * 16) as u8, 128])
+        }))
+    }
+
+    /// A JPEG segment with `marker` holding `data`.
+    fn jpeg_segment(marker: u8, data: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xFF, marker];
+        segment.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+        segment.extend_from_slice(data);
+        segment
+    }
+
+    /// A PNG chunk of `kind` holding `data`. The CRC isn't checked by anything here.
+    fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        chunk.extend_from_slice(&[0; 4]);
+        chunk
+    }
+
+    #[test]
+    fn jpeg_metadata_is_stripped() {
+        let clean = encode_image(&test_image(), ImageFormat::Jpeg, 90).unwrap();
+        let mut tagged = clean[..2].to_vec();
+        tagged.extend(jpeg_segment(0xE1, b"Exif\0\0GPS 51.5N 0.1W"));
+        tagged.extend(jpeg_segment(0xFE, b"taken by somebody"));
+        tagged.extend(jpeg_segment(0xED, b"Photoshop 3.0\0"));
+        tagged.extend_from_slice(&clean[2..]);
+
+        let stripped = strip_jpeg(&tagged).unwrap();
+        assert_eq!(stripped, clean);
+        let decoded = image::load_from_memory_with_format(&stripped, ImageFormat::Jpeg).unwrap();
+        let original = image::load_from_memory_with_format(&tagged, ImageFormat::Jpeg).unwrap();
+        assert_eq!(decoded.as_bytes(), original.as_bytes());
+    }
+
+    #[test]
+    fn png_metadata_is_stripped() {
+        let clean = encode_image(&test_image(), ImageFormat::Png, 90).unwrap();
+        // The signature and IHDR come first.
+        let header = 8 + 12 + 13;
+        let mut tagged = clean[..header].to_vec();
+        tagged.extend(png_chunk(b"tEXt", b"Author\0somebody"));
+        tagged.extend(png_chunk(b"tIME", &[0x07, 0xE6, 6, 10, 12, 0, 0]));
+        tagged.extend(png_chunk(b"eXIf", b"MM\0*"));
+        tagged.extend_from_slice(&clean[header..]);
+
+        assert_eq!(strip_png(&tagged).unwrap(), clean);
+    }
+
+    #[test]
+    fn strip_metadata_keeps_upright_pixels() {
+        let image = test_image();
+        let clean = encode_image(&image, ImageFormat::Jpeg, 90).unwrap();
+        let mut tagged = clean[..2].to_vec();
+        tagged.extend(jpeg_segment(0xE1, b"Exif\0\0"));
+        tagged.extend_from_slice(&clean[2..]);
+        let stripped = strip_metadata(&tagged, ImageFormat::Jpeg, 1, &image).unwrap();
+        assert_eq!(&*stripped, clean.as_slice());
+    }
+
+    #[test]
+    fn rotated_images_are_reencoded_without_metadata() {
+        let image = test_image();
+        let clean = encode_image(&image, ImageFormat::Jpeg, 90).unwrap();
+        let mut tagged = clean[..2].to_vec();
+        tagged.extend(jpeg_segment(0xE1, b"Exif\0\0"));
+        tagged.extend_from_slice(&clean[2..]);
+        let stripped = strip_metadata(&tagged, ImageFormat::Jpeg, 6, &image).unwrap();
+        assert!(!stripped.windows(4).any(|w| w == b"Exif"));
+        assert!(image::load_from_memory_with_format(&stripped, ImageFormat::Jpeg).is_ok());
+    }
+
+    #[test]
+    fn broken_files_arent_taken_apart() {
+        let clean = encode_image(&test_image(), ImageFormat::Jpeg, 90).unwrap();
+        assert_eq!(strip_jpeg(b"GIF89a"), None);
+        assert_eq!(strip_jpeg(&clean[..20]), None);
+        assert_eq!(strip_png(b"\x89PNG\r\n\x1a\n\0\0"), None);
+    }
+
+    #[test]
+    fn other_formats_are_kept_as_they_are() {
+        let image = test_image();
+        let buf = b"GIF89a...";
+        let kept = strip_metadata(buf, ImageFormat::Gif, 1, &image).unwrap();
+        assert!(matches!(kept, Cow::Borrowed(_)));
+    }
+
+    fn ban_duration(value: &str) -> Option<Duration> {
+        BanDuration::from_value(rocket::form::ValueField::from_value(value))
+            .ok()
+            .map(|duration| duration.0)
+    }
+
+    #[test]
+    fn ban_durations_take_a_count_and_a_unit() {
+        assert_eq!(ban_duration("30m"), Some(Duration::from_secs(30 * 60)));
+        assert_eq!(ban_duration("12h"), Some(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(
+            ban_duration("3d"),
+            Some(Duration::from_secs(3 * 24 * 60 * 60))
+        );
+        assert_eq!(
+            ban_duration(" 2w "),
+            Some(Duration::from_secs(2 * 7 * 24 * 60 * 60))
+        );
+    }
+
+    #[test]
+    fn perm_bans_last_a_century() {
+        assert_eq!(
+            ban_duration("perm"),
+            Some(Duration::from_secs(100 * 365 * 24 * 60 * 60))
+        );
+    }
+
+    #[test]
+    fn bad_ban_durations_are_refused() {
+        for value in ["", "7", "7y", "d", "-1d", "1.5h", "seven days", "7д"] {
+            assert_eq!(ban_duration(value), None, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn standalone_board_mentions_are_found() {
+        assert_eq!(board_mentions("/b/"), vec![(0..3, "b")]);
+        assert_eq!(board_mentions("check /g/ out"), vec![(6..9, "g")]);
+        assert_eq!(
+            board_mentions("/g/ and /v/"),
+            vec![(0..3, "g"), (8..11, "v")]
+        );
+    }
+
+    #[test]
+    fn board_mentions_may_be_wrapped_in_punctuation() {
+        assert_eq!(board_mentions("(/g/)"), vec![(1..4, "g")]);
+        assert_eq!(board_mentions("go to /g/."), vec![(6..9, "g")]);
+        assert_eq!(board_mentions(">/g/ is slow"), vec![(1..4, "g")]);
+    }
+
+    #[test]
+    fn paths_and_cross_board_links_are_not_board_mentions() {
+        assert!(board_mentions("https://example.com/a/b").is_empty());
+        assert!(board_mentions("/usr/bin/").is_empty());
+        assert!(board_mentions(">>>/b/1").is_empty());
+        assert!(board_mentions("/G/").is_empty());
+    }
+
+    #[test]
+    fn search_terms_match_every_word_by_prefix() {
+        assert_eq!(search_terms("rust"), "rust:*");
+        assert_eq!(
+            search_terms("Rust  Borrow checker"),
+            "rust:* & borrow:* & checker:*"
+        );
+        assert_eq!(search_terms("Привет мир"), "привет:* & мир:*");
+    }
+
+    #[test]
+    fn search_terms_drop_tsquery_syntax() {
+        assert_eq!(search_terms("a & !b | (c:*)"), "a:* & b:* & c:*");
+        assert_eq!(search_terms("it's"), "it:* & s:*");
+    }
+
+    #[test]
+    fn search_without_words_has_no_terms() {
+        assert_eq!(search_terms(""), "");
+        assert_eq!(search_terms("  &|!()  "), "");
+    }
+
+    #[test]
+    fn empty_honeypot_lets_the_post_through() {
+        let form = rocket::form::Form::<PostForm>::parse("board=b&content=hi").unwrap();
+        assert_eq!(form.honeypot, None);
+        let form = rocket::form::Form::<PostForm>::parse("board=b&content=hi&website=").unwrap();
+        assert_eq!(form.honeypot, None);
+        assert_eq!(&*form.board, "b");
+        assert_eq!(form.content.as_deref(), Some("hi"));
+    }
+
+    #[test]
+    fn filled_honeypot_is_caught() {
+        let form = rocket::form::Form::<PostForm>::parse("board=b&website=http://spam").unwrap();
+        assert_eq!(form.honeypot, Some("http://spam"));
+    }
+
+    #[test]
+    fn hashed_ips_are_keyed_with_the_salt() {
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let expected: IpNetwork = "fdb9:ebf7:391b:8e43:8ce7:7ceb:d07c:3f46".parse().unwrap();
+        assert_eq!(hashed_ip(ip, "pepper"), expected);
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        let expected: IpNetwork = "fd2f:fc88:d7c5:54c0:2cb3:258:a2e6:930f".parse().unwrap();
+        assert_eq!(hashed_ip(ip, "pepper"), expected);
+    }
+
+    #[test]
+    fn hashed_ips_depend_on_the_salt_and_address() {
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        assert_eq!(hashed_ip(ip, "pepper"), hashed_ip(ip, "pepper"));
+        assert_ne!(hashed_ip(ip, "pepper"), hashed_ip(ip, "salt"));
+        assert_ne!(hashed_ip(ip, "pepper"), hashed_ip(other, "pepper"));
+    }
+
+    fn gif(frames: &[[u8; 4]]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        {
+            let mut encoder = image::codecs::gif::GifEncoder::new(&mut buf);
+            encoder
+                .encode_frames(frames.iter().map(|&color| {
+                    image::Frame::from_parts(
+                        image::RgbaImage::from_pixel(8, 8, image::Rgba(color)),
+                        0,
+                        0,
+                        image::Delay::from_numer_denom_ms(100, 1),
+                    )
+                }))
+                .unwrap();
+        }
+        buf
+    }
+
+    #[test]
+    fn animated_gifs_keep_every_frame() {
+        let source = gif(&[[255, 0, 0, 255], [0, 0, 255, 255]]);
+        let thumb = animated_thumbnail(&source).unwrap().unwrap();
+        let frames = image::codecs::gif::GifDecoder::new(std::io::Cursor::new(thumb))
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        assert_eq!(frames.len(), 2);
+        for frame in &frames {
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            assert_eq!(numer / denom, 100);
+        }
+    }
+
+    #[test]
+    fn still_gifs_get_a_still_thumbnail() {
+        let source = gif(&[[255, 0, 0, 255]]);
+        assert!(animated_thumbnail(&source).unwrap().is_none());
+    }
+
+    fn rgba(alpha: u8) -> DynamicImage {
+        DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            4,
+            4,
+            image::Rgba([9, 9, 9, alpha]),
+        ))
+    }
+
+    #[test]
+    fn transparent_thumbnails_stay_png() {
+        assert_eq!(
+            thumb_format_with(&rgba(0), Some(ImageFormat::Gif), ThumbFormat::Auto),
+            ImageFormat::Png
+        );
+        assert_eq!(
+            thumb_format_with(&rgba(128), Some(ImageFormat::WebP), ThumbFormat::Auto),
+            ImageFormat::Png
+        );
+    }
+
+    #[test]
+    fn opaque_thumbnails_become_jpeg() {
+        assert_eq!(
+            thumb_format_with(&rgba(255), Some(ImageFormat::Gif), ThumbFormat::Auto),
+            ImageFormat::Jpeg
+        );
+        let photo = DynamicImage::new_rgb8(4, 4);
+        assert_eq!(
+            thumb_format_with(&photo, Some(ImageFormat::Jpeg), ThumbFormat::Auto),
+            ImageFormat::Jpeg
+        );
+        assert_eq!(
+            thumb_format_with(&photo, None, ThumbFormat::Auto),
+            ImageFormat::Jpeg
+        );
+    }
+
+    #[test]
+    fn png_sources_keep_png_thumbnails() {
+        let opaque = DynamicImage::new_rgb8(4, 4);
+        assert_eq!(
+            thumb_format_with(&opaque, Some(ImageFormat::Png), ThumbFormat::Auto),
+            ImageFormat::Png
+        );
+    }
+
+    #[test]
+    fn thumb_format_setting_overrides_the_heuristic() {
+        assert_eq!(
+            thumb_format_with(&rgba(0), Some(ImageFormat::Png), ThumbFormat::Jpeg),
+            ImageFormat::Jpeg
+        );
+        let photo = DynamicImage::new_rgb8(4, 4);
+        assert_eq!(
+            thumb_format_with(&photo, Some(ImageFormat::Jpeg), ThumbFormat::Png),
+            ImageFormat::Png
+        );
+    }
+
+    /// A 3x2 image with its top-left pixel marked.
+    fn marked() -> DynamicImage {
+        let mut image = image::RgbImage::new(3, 2);
+        image.put_pixel(0, 0, image::Rgb([255, 0, 0]));
+        DynamicImage::ImageRgb8(image)
+    }
+
+    #[test]
+    fn sideways_photos_are_turned_upright() {
+        // Orientation 6 means the camera was turned clockwise, so the image needs the same.
+        let upright = apply_orientation(marked(), 6);
+        assert_eq!(upright.dimensions(), (2, 3));
+        assert_eq!(upright.get_pixel(1, 0), image::Rgba([255, 0, 0, 255]));
+
+        let upright = apply_orientation(marked(), 8);
+        assert_eq!(upright.dimensions(), (2, 3));
+        assert_eq!(upright.get_pixel(0, 2), image::Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn upside_down_photos_are_turned_upright() {
+        let upright = apply_orientation(marked(), 3);
+        assert_eq!(upright.dimensions(), (3, 2));
+        assert_eq!(upright.get_pixel(2, 1), image::Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn upright_and_unknown_orientations_are_left_alone() {
+        for orientation in [0, 1, 9] {
+            let image = apply_orientation(marked(), orientation);
+            assert_eq!(image.dimensions(), (3, 2));
+            assert_eq!(image.get_pixel(0, 0), image::Rgba([255, 0, 0, 255]));
+        }
+    }
+
+    #[test]
+    fn missing_exif_means_upright() {
+        let png = encode_image(&marked(), ImageFormat::Png, 85).unwrap();
+        assert_eq!(exif_orientation(&png), 1);
+    }
+
+    /// Needs a database at `DATABASE_URL`, run with `cargo test -- --ignored`.
+    #[rocket::async_test]
+    #[ignore]
+    async fn concurrent_threads_get_distinct_contiguous_ids() {
+        const THREADS: usize = 32;
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        // Each post holds a connection for its transaction and may take another to render,
+        // so every task gets enough to not wait on the others.
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .max_connections(2 * THREADS as u32)
+            .connect(&url)
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let name = Board::create(
+            &format!("race{}", nanos % 1_000_000_000),
+            "Race",
+            None,
+            false,
+            &pool,
+        )
+        .await
+        .unwrap();
+        // Text-only, so that threads can be started without uploading anything.
+        sqlx::query("UPDATE boards SET text_only = TRUE WHERE name = $1")
+            .bind(&name)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let tasks: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let pool = pool.clone();
+                let name = name.clone();
+                tokio::spawn(async move {
+                    let board = Board::get(&name, &pool).await.unwrap().unwrap();
+                    let ip: IpAddr = "192.0.2.1".parse().unwrap();
+                    Post::create_thread(
+                        &board,
+                        None,
+                        None,
+                        None,
+                        false,
+                        Some("race"),
+                        ip.into(),
+                        &[],
+                        Vec::new(),
+                        &pool,
+                    )
+                    .await
+                    .unwrap()
+                })
+            })
+            .collect();
+        let mut ids = Vec::with_capacity(THREADS);
+        for task in tasks {
+            ids.push(task.await.unwrap());
+        }
+        Board::delete(&name, false, &pool).await.unwrap();
+
+        ids.sort_unstable();
+        let first = ids[0];
+        assert_eq!(ids, (first..first + THREADS as i32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn admins_pass_both_guards() {
+        assert!(PrivelegeLevel::Admin.allows(PrivelegeLevel::Admin));
+        assert!(PrivelegeLevel::Admin.allows(PrivelegeLevel::Mod));
+    }
+
+    #[test]
+    fn mods_only_pass_the_mod_guard() {
+        assert!(PrivelegeLevel::Mod.allows(PrivelegeLevel::Mod));
+        assert!(!PrivelegeLevel::Mod.allows(PrivelegeLevel::Admin));
+    }
 }
@@ -0,0 +1,275 @@
+use crate::{errors::Error, models::stored_ip};
+use rocket::async_trait;
+use sqlx::{query, PgPool};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Cooldowns for actions that leave no other trace, currently just captcha checks.
+/// Posting cooldowns are checked against the posts themselves, see `Post::last_post_at_for_ip`.
+#[async_trait]
+pub trait RateLimiter: Send + Sync {
+    /// Record an attempt at `action` from `ip`.
+    /// Returns the remaining wait if the previous attempt was less than `cooldown` ago,
+    /// in which case the attempt is not recorded.
+    async fn hit(
+        &self,
+        ip: IpAddr,
+        action: &str,
+        cooldown: Duration,
+    ) -> Result<Option<Duration>, Error>;
+}
+
+/// How often the in-memory limiter sweeps out attempts whose cooldown is over.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Keeps the last attempt of every client in process memory.
+#[derive(Default)]
+pub struct MemoryRateLimiter {
+    hits: Mutex<Hits>,
+}
+
+#[derive(Default)]
+struct Hits {
+    last: HashMap<(IpAddr, String), (Instant, Duration)>,
+    pruned_at: Option<Instant>,
+}
+
+impl Hits {
+    /// Forget the attempts whose cooldown is over as of `now`, at most once per `PRUNE_INTERVAL`.
+    /// Lookups check the cooldown themselves, so entries left over in between do no harm.
+    fn prune(&mut self, now: Instant) {
+        if self
+            .pruned_at
+            .map_or(false, |at| now.duration_since(at) < PRUNE_INTERVAL)
+        {
+            return;
+        }
+        self.last
+            .retain(|_, (last, cooldown)| now.duration_since(*last) < *cooldown);
+        self.pruned_at = Some(now);
+    }
+}
+
+#[async_trait]
+impl RateLimiter for MemoryRateLimiter {
+    async fn hit(
+        &self,
+        ip: IpAddr,
+        action: &str,
+        cooldown: Duration,
+    ) -> Result<Option<Duration>, Error> {
+        let now = Instant::now();
+        let mut hits = self.hits.lock().unwrap();
+        hits.prune(now);
+
+        let key = (ip, action.to_owned());
+        if let Some((last, _)) = hits.last.get(&key) {
+            let elapsed = now.duration_since(*last);
+            if elapsed < cooldown {
+                return Ok(Some(cooldown - elapsed));
+            }
+        }
+        hits.last.insert(key, (now, cooldown));
+        Ok(None)
+    }
+}
+
+/// Keeps the last attempt of every client in the `rate_limits` table,
+/// so that limits hold across multiple instances.
+pub struct PgRateLimiter {
+    pool: PgPool,
+}
+
+impl PgRateLimiter {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl RateLimiter for PgRateLimiter {
+    async fn hit(
+        &self,
+        ip: IpAddr,
+        action: &str,
+        cooldown: Duration,
+    ) -> Result<Option<Duration>, Error> {
+        let ip = stored_ip(ip);
+        let cooldown = cooldown.as_secs_f64();
+        let recorded = query!(
+            "INSERT INTO rate_limits(ip, action, last_hit)
+            VALUES ($1, $2, NOW())
+            ON CONFLICT (ip, action) DO UPDATE
+                SET last_hit = NOW()
+                WHERE rate_limits.last_hit + make_interval(secs => $3) <= NOW()
+            RETURNING last_hit",
+            ip,
+            action,
+            cooldown
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        if recorded.is_some() {
+            return Ok(None);
+        }
+
+        let wait = query!(
+            r#"SELECT CAST(EXTRACT(EPOCH FROM last_hit + make_interval(secs => $3) - NOW()) AS FLOAT8) AS "wait!"
+            FROM rate_limits
+            WHERE ip = $1 AND action = $2"#,
+            ip,
+            action,
+            cooldown
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .wait;
+        Ok(Some(Duration::from_secs_f64(wait.max(0.0))))
+    }
+}
+
+/// Tracks how many uploads each client has in flight.
+#[derive(Default)]
+pub struct UploadSlots {
+    in_flight: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl UploadSlots {
+    /// Take one of `ip`'s `max` upload slots, if it has any left.
+    /// The slot is given back when the guard is dropped.
+    pub fn acquire(&self, ip: IpAddr, max: usize) -> Option<UploadSlot<'_>> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let count = in_flight.entry(ip).or_default();
+        if *count >= max {
+            return None;
+        }
+        *count += 1;
+        Some(UploadSlot { slots: self, ip })
+    }
+}
+
+pub struct UploadSlot<'s> {
+    slots: &'s UploadSlots,
+    ip: IpAddr,
+}
+
+impl Drop for UploadSlot<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.slots.in_flight.lock().unwrap();
+        if let Some(count) = in_flight.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COOLDOWN: Duration = Duration::from_millis(100);
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    /// The cooldown behaviour both backends have to agree on.
+    /// `action` should be fresh for every run, since the Postgres backend keeps its state.
+    async fn enforces_cooldown(limiter: &dyn RateLimiter, action: &str) {
+        let client = ip("192.0.2.1");
+        assert_eq!(limiter.hit(client, action, COOLDOWN).await.unwrap(), None);
+        let wait = limiter
+            .hit(client, action, COOLDOWN)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(wait > Duration::ZERO && wait <= COOLDOWN);
+
+        // Refused hits don't restart the cooldown.
+        tokio::time::sleep(COOLDOWN / 2).await;
+        assert!(limiter
+            .hit(client, action, COOLDOWN)
+            .await
+            .unwrap()
+            .is_some());
+        // A little extra, so that this doesn't hinge on the order of two clocks.
+        tokio::time::sleep(COOLDOWN / 2 + COOLDOWN / 10).await;
+        assert_eq!(limiter.hit(client, action, COOLDOWN).await.unwrap(), None);
+        assert!(limiter
+            .hit(client, action, COOLDOWN)
+            .await
+            .unwrap()
+            .is_some());
+
+        // Other clients and actions have cooldowns of their own.
+        let other = ip("192.0.2.2");
+        assert_eq!(limiter.hit(other, action, COOLDOWN).await.unwrap(), None);
+        let other_action = format!("{action}_other");
+        assert_eq!(
+            limiter.hit(client, &other_action, COOLDOWN).await.unwrap(),
+            None
+        );
+    }
+
+    #[rocket::async_test]
+    async fn memory_limiter_enforces_the_cooldown() {
+        enforces_cooldown(&MemoryRateLimiter::default(), "test").await;
+    }
+
+    /// Needs a database at `DATABASE_URL`, run with `cargo test -- --ignored`.
+    #[rocket::async_test]
+    #[ignore]
+    async fn postgres_limiter_enforces_the_cooldown() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(&url).await.unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let action = format!("test_{nanos}");
+        enforces_cooldown(&PgRateLimiter::new(pool.clone()), &action).await;
+        sqlx::query("DELETE FROM rate_limits WHERE action LIKE $1")
+            .bind(format!("{action}%"))
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn expired_hits_are_swept_out_periodically() {
+        let limiter = MemoryRateLimiter::default();
+        limiter
+            .hit(ip("192.0.2.1"), "post", COOLDOWN)
+            .await
+            .unwrap();
+        limiter
+            .hit(ip("192.0.2.2"), "post", COOLDOWN)
+            .await
+            .unwrap();
+        let mut hits = limiter.hits.lock().unwrap();
+
+        // Within the interval expired hits are left for lookups to skip over.
+        hits.prune(Instant::now() + COOLDOWN);
+        assert_eq!(hits.last.len(), 2);
+
+        hits.prune(Instant::now() + PRUNE_INTERVAL);
+        assert!(hits.last.is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn unexpired_hits_survive_a_sweep() {
+        let limiter = MemoryRateLimiter::default();
+        let long = PRUNE_INTERVAL * 2;
+        limiter.hit(ip("192.0.2.1"), "post", long).await.unwrap();
+        let mut hits = limiter.hits.lock().unwrap();
+        hits.prune(Instant::now() + PRUNE_INTERVAL);
+        assert_eq!(hits.last.len(), 1);
+    }
+}
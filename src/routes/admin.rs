@@ -1,60 +1,618 @@
 use super::public;
 use crate::{
+    config::CONFIG,
     errors::Error,
-    models::{AdminPrivilege, Board, BoardForm, LoginForm},
+    models::{
+        stored_ip, AdminPrivilege, Ban, BanForm, Board, BoardEditForm, BoardForm, CssForm,
+        DeleteBoardForm, Image, LoginForm, MaintenanceWindow, ModPrivilege, Post, PostCount,
+        Report, Session, Stats, StatsBucket,
+    },
 };
 use maud::{html, Markup};
-use rocket::{form::Form, get, post, response::Redirect, uri, State};
-use sqlx::PgPool;
+use rocket::{
+    form::Form,
+    get,
+    http::{CookieJar, Status},
+    post,
+    response::Redirect,
+    uri, Either, State,
+};
+use sqlx::{
+    types::{
+        ipnetwork::IpNetwork,
+        time::{Duration, OffsetDateTime, PrimitiveDateTime},
+    },
+    PgPool,
+};
+use std::net::IpAddr;
 
 #[get("/admin")]
 pub async fn index(pool: &State<PgPool>, privilege: AdminPrivilege) -> Result<Markup, Error> {
+    dashboard(pool, &privilege, None).await
+}
+
+/// The admin index, with `error` from a failed board creation shown above the board form.
+async fn dashboard(
+    pool: &PgPool,
+    privilege: &AdminPrivilege,
+    error: Option<&str>,
+) -> Result<Markup, Error> {
+    let stats = Stats::gather(pool).await?;
+    let boards = Board::get_all(pool).await?;
     Ok(html! {
         head {
             link rel="stylesheet" href="/static/style.css";
         }
         body {
             h1 { (format!("Hello {}", privilege.uid())) }
+            table.dashboard {
+                tbody {
+                    tr { td { "Boards" } td { (stats.boards()) } }
+                    tr { td { "Threads" } td { (stats.threads()) } }
+                    tr { td { "Posts" } td { (stats.posts()) } }
+                    tr { td { "Posts today" } td { (stats.posts_today()) } }
+                    tr {
+                        td { a href=(uri!(bans(_, _)).to_string()) { "Active bans" } }
+                        td { (stats.active_bans()) }
+                    }
+                    tr {
+                        td { a href=(uri!(reports).to_string()) { "Open reports" } }
+                        td { (stats.open_reports()) }
+                    }
+                }
+            }
+            table.boards {
+                tbody {
+                    @for board in &boards {
+                        tr {
+                            td {
+                                a href=(uri!(public::board(board.name(), _, _, _)).to_string()) {
+                                    (format!("/{}/ - {}", board.name(), board.title()))
+                                }
+                            }
+                            td { a href=(uri!(edit_board_page(board.name())).to_string()) { "Edit" } }
+                            td { a href=(uri!(board_css_page(board.name())).to_string()) { "Stylesheet" } }
+                            td { a href=(uri!(delete_board_page(board.name())).to_string()) { "Delete" } }
+                        }
+                    }
+                }
+            }
+            @if let Some(error) = error {
+                .error { p { (error) } }
+            }
+            div {
+                form id="board" action=(uri!(create_board).to_string()) method="post" {
+                    label for="name" { "Name" }
+                    input type="text" name="name";br;
+                    label for="title" { "Title" }
+                    input type="text" name="title";br;
+                    label for="description" { "Description" }
+                    textarea name="description" form="board" rows="4" cols="60" {}br;
+                    label for="require_content_for_op" { "Require text for new threads" }
+                    input type="checkbox" name="require_content_for_op";br;
+                    input type="submit";
+                }
+            }
         }
     })
 }
 
 #[get("/admin", rank = 2)]
-pub async fn login_page(pool: &State<PgPool>) -> Result<Markup, Error> {
-    Ok(html! {
+pub async fn login_page() -> Markup {
+    login_form(None)
+}
+
+#[post("/admin/login", data = "<form>")]
+pub async fn login(
+    pool: &State<PgPool>,
+    form: Form<LoginForm<'_>>,
+    cookies: &CookieJar<'_>,
+) -> Result<Either<Redirect, (Status, Markup)>, Error> {
+    match Session::new(&form.name, &form.password, pool).await {
+        Ok(session) => {
+            cookies.add_private(super::cookie("sessionid", session.id().to_string()));
+            Ok(Either::Left(Redirect::to(uri!(index))))
+        }
+        Err(e @ Error::InvalidCredentials) => Ok(Either::Right((
+            Status::Unauthorized,
+            login_form(Some(&e.to_string())),
+        ))),
+        Err(e) => Err(e),
+    }
+}
+
+fn login_form(error: Option<&str>) -> Markup {
+    html! {
         head {
             link rel="stylesheet" href="/static/style.css";
         }
         body {
             h1 { "Hello, ruburu!" }
+            @if let Some(error) = error {
+                .error { p { (error) } }
+            }
             div {
-                form id="board" action=(uri!(create_board).to_string()) method="post" {
+                form id="login" action=(uri!(login).to_string()) method="post" {
                     label for="name" { "Name" }
-                    input type="text" name="name";br;
-                    label for="title" { "Title" }
-                    input type="text" name="title";br;
+                    input type="text" name="name" id="name";br;
+                    label for="password" { "Password" }
+                    input type="password" name="password" id="password";br;
                     input type="submit";
                 }
             }
         }
+    }
+}
+
+#[post("/admin/submit", data = "<form>")]
+pub async fn create_board(
+    pool: &State<PgPool>,
+    form: Form<BoardForm<'_>>,
+    privilege: AdminPrivilege,
+) -> Result<Either<Redirect, (Status, Markup)>, Error> {
+    MaintenanceWindow::ensure_writable(pool).await?;
+    let form = form.into_inner();
+    let created = Board::create(
+        form.name.as_ref(),
+        form.title.as_ref(),
+        form.description.as_deref(),
+        form.require_content_for_op,
+        pool,
+    )
+    .await;
+    match created {
+        Ok(name) => Ok(Either::Left(Redirect::to(uri!(public::board(
+            name,
+            _,
+            _,
+            _
+        ))))),
+        Err(e @ (Error::BoardExists | Error::InvalidBoardName | Error::TooLong(..))) => {
+            let status = match e {
+                Error::BoardExists => Status::Conflict,
+                _ => Status::UnprocessableEntity,
+            };
+            let page = dashboard(pool, &privilege, Some(&e.to_string())).await?;
+            Ok(Either::Right((status, page)))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[post("/admin/rebuild-backlinks/<board>")]
+pub async fn rebuild_backlinks(
+    board: &str,
+    pool: &State<PgPool>,
+    _privilege: AdminPrivilege,
+) -> Result<Redirect, Error> {
+    let board = Board::get(board, pool).await?.ok_or(Error::NotFound)?;
+    Post::rebuild_replies(&board, pool).await?;
+    Ok(Redirect::to(uri!(public::board(board.name(), _, _, _))))
+}
+
+#[get("/admin/edit-board/<board>")]
+pub async fn edit_board_page(
+    board: &str,
+    pool: &State<PgPool>,
+    _privilege: AdminPrivilege,
+) -> Result<Markup, Error> {
+    let board = Board::get(board, pool).await?.ok_or(Error::NotFound)?;
+    Ok(html! {
+        head {
+            link rel="stylesheet" href="/static/style.css";
+        }
+        body {
+            h1 { (format!("Edit /{}/", board.name())) }
+            form id="edit" action=(uri!(edit_board(board.name())).to_string()) method="post" {
+                label for="title" { "Title" }
+                input type="text" name="title" value=(board.title());br;
+                label for="description" { "Description" }br;
+                textarea name="description" form="edit" rows="8" cols="80" { (board.description().unwrap_or_default()) }br;
+                input type="submit";
+            }
+        }
     })
 }
-#[post("/admin/login", data = "<form>")]
-pub async fn login(
+
+#[post("/admin/edit-board/<board>", data = "<form>")]
+pub async fn edit_board(
+    board: &str,
+    form: Form<BoardEditForm<'_>>,
     pool: &State<PgPool>,
-    form: Form<LoginForm<'_>>,
     _privilege: AdminPrivilege,
 ) -> Result<Redirect, Error> {
+    Board::update(board, &form.title, form.description.as_deref(), pool).await?;
+    Ok(Redirect::to(uri!(public::board(board, _, _, _))))
+}
+
+#[get("/admin/delete-board/<board>")]
+pub async fn delete_board_page(
+    board: &str,
+    pool: &State<PgPool>,
+    _privilege: AdminPrivilege,
+) -> Result<Markup, Error> {
+    let board = Board::get(board, pool).await?.ok_or(Error::NotFound)?;
+    let threads = Post::thread_count(board.name(), pool).await?;
+    Ok(html! {
+        head {
+            link rel="stylesheet" href="/static/style.css";
+        }
+        body {
+            h1 { (format!("Delete /{}/", board.name())) }
+            p { (format!("This deletes the board and its {threads} threads for good.")) }
+            form action=(uri!(delete_board(board.name())).to_string()) method="post" {
+                label for="confirm" { "Type the board's name to confirm" }
+                input type="text" name="confirm";br;
+                label for="purge_images" { "Also delete images no other board uses" }
+                input type="checkbox" name="purge_images";br;
+                input type="submit" value="Delete";
+            }
+        }
+    })
+}
+
+#[post("/admin/delete-board/<board>", data = "<form>")]
+pub async fn delete_board(
+    board: &str,
+    form: Form<DeleteBoardForm<'_>>,
+    pool: &State<PgPool>,
+    _privilege: AdminPrivilege,
+) -> Result<Redirect, Error> {
+    if form.confirm.trim() != board {
+        return Err(Error::ConfirmationMismatch);
+    }
+    Board::delete(board, form.purge_images, pool).await?;
+    tracing::info!("Deleted board /{board}/");
     Ok(Redirect::to(uri!(index)))
 }
 
-#[post("/admin/submit", data = "<form>")]
-pub async fn create_board(
+#[get("/admin/board-css/<board>")]
+pub async fn board_css_page(
+    board: &str,
+    pool: &State<PgPool>,
+    _privilege: AdminPrivilege,
+) -> Result<Markup, Error> {
+    let board = Board::get(board, pool).await?.ok_or(Error::NotFound)?;
+    Ok(html! {
+        head {
+            link rel="stylesheet" href="/static/style.css";
+        }
+        body {
+            h1 { (format!("Stylesheet for /{}/", board.name())) }
+            form id="css" action=(uri!(set_board_css(board.name())).to_string()) method="post" {
+                textarea name="css" form="css" rows="24" cols="80" { (board.custom_css().unwrap_or_default()) }br;
+                input type="submit";
+            }
+        }
+    })
+}
+
+#[post("/admin/board-css/<board>", data = "<form>")]
+pub async fn set_board_css(
+    board: &str,
+    form: Form<CssForm<'_>>,
     pool: &State<PgPool>,
-    form: Form<BoardForm<'_>>,
     _privilege: AdminPrivilege,
 ) -> Result<Redirect, Error> {
-    let form = form.into_inner();
-    Board::create(form.name.as_ref(), form.title.as_ref(), pool).await?;
-    Ok(Redirect::to(uri!(public::board(form.name.as_ref()))))
+    let board = Board::get(board, pool).await?.ok_or(Error::NotFound)?;
+    Board::set_custom_css(board.name(), form.css.as_deref(), pool).await?;
+    Ok(Redirect::to(uri!(public::board(board.name(), _, _, _))))
+}
+
+/// Blocks every image attached to the post.
+#[post("/admin/block-image/<board>/<id>")]
+pub async fn block_image(
+    board: &str,
+    id: i32,
+    pool: &State<PgPool>,
+    _privilege: ModPrivilege,
+) -> Result<Redirect, Error> {
+    let post = Post::get(board, id, pool).await?.ok_or(Error::NotFound)?;
+    let images = post.images(pool).await?;
+    if images.is_empty() {
+        return Err(Error::NotFound);
+    }
+    for hash in images {
+        Image::block(hash, pool).await?;
+    }
+    Ok(Redirect::to(uri!(public::thread(
+        post.board(),
+        post.thread(),
+        _
+    ))))
+}
+
+#[post("/admin/delete-post/<board>/<id>")]
+pub async fn delete_post(
+    board: &str,
+    id: i32,
+    pool: &State<PgPool>,
+    _privilege: ModPrivilege,
+) -> Result<Redirect, Error> {
+    let thread = Post::delete(board, id, pool).await?;
+    if thread == id {
+        Ok(Redirect::to(uri!(public::board(board, _, _, _))))
+    } else {
+        Ok(Redirect::to(uri!(public::thread(board, thread, _))))
+    }
+}
+
+/// `?locked=true` locks the thread, anything else unlocks it.
+#[post("/admin/lock-thread/<board>/<thread>?<locked>")]
+pub async fn lock_thread(
+    board: &str,
+    thread: i32,
+    locked: bool,
+    pool: &State<PgPool>,
+    _privilege: ModPrivilege,
+) -> Result<Redirect, Error> {
+    Post::set_locked(board, thread, locked, pool).await?;
+    Ok(Redirect::to(uri!(public::thread(board, thread, _))))
+}
+
+/// Pins the thread if it isn't, for `days` days if given, and unpins it otherwise.
+#[post("/admin/toggle-sticky/<board>/<thread>?<days>")]
+pub async fn toggle_sticky(
+    board: &str,
+    thread: i32,
+    days: Option<i64>,
+    pool: &State<PgPool>,
+    _privilege: ModPrivilege,
+) -> Result<Redirect, Error> {
+    let op = Post::get(board, thread, pool)
+        .await?
+        .filter(|p| p.id() == p.thread())
+        .ok_or(Error::NotFound)?;
+    let until = days.map(|days| {
+        let now = OffsetDateTime::now_utc() + Duration::days(days);
+        PrimitiveDateTime::new(now.date(), now.time())
+    });
+    Post::set_sticky(board, thread, !op.is_sticky(), until, pool).await?;
+    Ok(Redirect::to(uri!(public::board(board, _, _, _))))
+}
+
+#[get("/admin/reports")]
+pub async fn reports(pool: &State<PgPool>, _privilege: ModPrivilege) -> Result<Markup, Error> {
+    let reports = Report::open(pool).await?;
+    Ok(html! {
+        head {
+            link rel="stylesheet" href="/static/style.css";
+        }
+        body {
+            h1 { "Reports" }
+            table.reports {
+                tbody {
+                    @for report in &reports {
+                        tr {
+                            td {
+                                a href=(uri!(public::permalink(report.board(), report.post_id())).to_string()) {
+                                    (format!(">>/{}/{}", report.board(), report.post_id()))
+                                }
+                            }
+                            td { (report.reason()) }
+                            td { (report.count()) }
+                            td { (report.created_at().format("%Y-%m-%d %H:%M")) }
+                            td {
+                                form action=(uri!(dismiss_report(report.id())).to_string()) method="post" {
+                                    input type="submit" value="Dismiss";
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[post("/admin/reports/<id>/dismiss")]
+pub async fn dismiss_report(
+    id: i32,
+    pool: &State<PgPool>,
+    _privilege: ModPrivilege,
+) -> Result<Redirect, Error> {
+    Report::dismiss(id, pool).await?;
+    Ok(Redirect::to(uri!(reports)))
+}
+
+/// Active bans, and a form for adding one.
+/// Linking here with `?board=..&post=..` fills the form in to ban that post's author.
+#[get("/admin/bans?<board>&<post>")]
+pub async fn bans(
+    board: Option<&str>,
+    post: Option<i32>,
+    pool: &State<PgPool>,
+    _privilege: ModPrivilege,
+) -> Result<Markup, Error> {
+    let bans = Ban::list(pool).await?;
+    Ok(html! {
+        head {
+            link rel="stylesheet" href="/static/style.css";
+        }
+        body {
+            h1 { "Bans" }
+            form action=(uri!(ban).to_string()) method="post" {
+                table {
+                    tbody {
+                        @if let (Some(board), Some(post)) = (board, post) {
+                            tr {
+                                td { "Post" }
+                                td {
+                                    (format!(">>/{board}/{post}"))
+                                    input type="hidden" name="board" value=(board);
+                                    input type="hidden" name="post" value=(post);
+                                }
+                            }
+                        } @else {
+                            tr {
+                                td { "IP or range" }
+                                td { input type="text" name="ip"; }
+                            }
+                        }
+                        tr {
+                            td { "Reason" }
+                            td { input type="text" name="reason" maxlength="256"; }
+                        }
+                        tr {
+                            td { "Duration" }
+                            td { input type="text" name="duration" placeholder="30m, 12h, 7d, 2w or perm"; }
+                        }
+                    }
+                }
+                input type="submit" value="Ban";
+            }
+            table.bans {
+                tbody {
+                    @for ban in &bans {
+                        tr {
+                            td { (ban.ip()) }
+                            td { (ban.reason()) }
+                            td { (ban.created_at().format("%Y-%m-%d %H:%M")) }
+                            td { (ban.expires_at().format("%Y-%m-%d %H:%M")) }
+                            td {
+                                form action=(uri!(unban(ban.ip().to_string())).to_string()) method="post" {
+                                    input type="submit" value="Unban";
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[post("/admin/bans", data = "<form>")]
+pub async fn ban(
+    form: Form<BanForm<'_>>,
+    pool: &State<PgPool>,
+    _privilege: ModPrivilege,
+) -> Result<Redirect, Error> {
+    let ip = match (&form.board, form.post, &form.ip) {
+        (Some(board), Some(post), _) => Post::get(board, post, pool)
+            .await?
+            .ok_or(Error::NotFound)?
+            .ip(),
+        (_, _, Some(ip)) => ban_target(ip, CONFIG.ip_hash_salt.is_some())?,
+        _ => return Err(Error::InvalidBan("no IP or post given")),
+    };
+    if form.reason.len() > 256 {
+        return Err(Error::InvalidBan("the reason is too long"));
+    }
+    Ban::create(ip, &form.reason, form.duration.0, pool).await?;
+    Ok(Redirect::to(uri!(bans(_, _))))
+}
+
+/// Parse a mod-supplied IP or CIDR range into the form bans are stored in.
+/// `hashed` tells whether posters' IPs are stored hashed.
+fn ban_target(ip: &str, hashed: bool) -> Result<IpNetwork, Error> {
+    if let Ok(ip) = ip.parse::<IpAddr>() {
+        return Ok(stored_ip(ip));
+    }
+    let range: IpNetwork = ip
+        .parse()
+        .map_err(|_| Error::InvalidBan("not an IP or range"))?;
+    // Hashed IPs don't keep their neighbours close, so a range would match nothing.
+    if hashed {
+        return Err(Error::InvalidBan(
+            "ranges can't be banned while IPs are hashed",
+        ));
+    }
+    Ok(range)
+}
+
+#[post("/admin/bans/<ip>/remove")]
+pub async fn unban(
+    ip: &str,
+    pool: &State<PgPool>,
+    _privilege: ModPrivilege,
+) -> Result<Redirect, Error> {
+    let ip: IpNetwork = ip.parse().map_err(|_| Error::NotFound)?;
+    Ban::remove(ip, pool).await?;
+    Ok(Redirect::to(uri!(bans(_, _))))
+}
+
+#[get("/admin/stats?<board>&<bucket>&<days>")]
+pub async fn stats(
+    board: Option<&str>,
+    bucket: Option<StatsBucket>,
+    days: Option<i32>,
+    pool: &State<PgPool>,
+    _privilege: AdminPrivilege,
+) -> Result<Markup, Error> {
+    let bucket = bucket.unwrap_or(StatsBucket::Day);
+    let days = days.unwrap_or(CONFIG.stats_window_days);
+    let global = PostCount::series(bucket, None, days, pool).await?;
+    let board = match board {
+        Some(board) => {
+            let board = Board::get(board, pool).await?.ok_or(Error::NotFound)?;
+            let series = PostCount::series(bucket, Some(board.name()), days, pool).await?;
+            Some((board, series))
+        }
+        None => None,
+    };
+    Ok(html! {
+        head {
+            link rel="stylesheet" href="/static/style.css";
+        }
+        body {
+            h1 { (format!("Posts over the last {days} days")) }
+            (stats_table("All boards", &global))
+            @if let Some((board, series)) = &board {
+                (stats_table(&format!("/{}/", board.name()), series))
+            }
+        }
+    })
+}
+
+fn stats_table(caption: &str, series: &[PostCount]) -> Markup {
+    html! {
+        table.stats {
+            caption { (caption) }
+            tbody {
+                @for count in series {
+                    tr {
+                        td { (count.bucket().format("%Y-%m-%d %H:%M")) }
+                        td { (count.posts()) }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ban_target_takes_a_plain_ip() {
+        let ip: IpAddr = "192.0.2.7".parse().unwrap();
+        assert_eq!(ban_target("192.0.2.7", false).unwrap(), stored_ip(ip));
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        assert_eq!(ban_target("2001:db8::1", false).unwrap(), stored_ip(ip));
+    }
+
+    #[test]
+    fn ban_target_takes_a_range() {
+        let range: IpNetwork = "192.0.2.0/24".parse().unwrap();
+        assert_eq!(ban_target("192.0.2.0/24", false).unwrap(), range);
+        let range: IpNetwork = "2001:db8::/32".parse().unwrap();
+        assert_eq!(ban_target("2001:db8::/32", false).unwrap(), range);
+    }
+
+    #[test]
+    fn ban_target_refuses_ranges_while_ips_are_hashed() {
+        assert!(matches!(
+            ban_target("192.0.2.0/24", true),
+            Err(Error::InvalidBan(_))
+        ));
+    }
+
+    #[test]
+    fn ban_target_refuses_garbage() {
+        for ip in ["", "not an ip", "192.0.2.300", "192.0.2.0/33"] {
+            assert!(matches!(ban_target(ip, false), Err(Error::InvalidBan(_))));
+        }
+    }
 }
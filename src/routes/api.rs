@@ -0,0 +1,92 @@
+use crate::{
+    config::CONFIG,
+    errors::Error,
+    models::{Board, Post},
+};
+use rocket::{
+    get,
+    serde::{json::Json, Serialize},
+    State,
+};
+use sqlx::{types::Uuid, PgPool};
+use std::collections::HashMap;
+
+/// What the API shows of a post. Kept apart from [`Post`] so that the poster's IP
+/// and moderation state never end up in a response.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PostJson {
+    id: i32,
+    board: String,
+    thread: i32,
+    title: Option<String>,
+    author: Option<String>,
+    tripcode: Option<String>,
+    email: Option<String>,
+    sage: bool,
+    content: Option<String>,
+    /// Unix timestamp, in seconds.
+    posted_at: i64,
+    images: Vec<String>,
+}
+
+impl PostJson {
+    fn new(post: &Post, images: Vec<Uuid>) -> Self {
+        Self {
+            id: post.id(),
+            board: post.board().to_owned(),
+            thread: post.thread(),
+            title: post.title().map(str::to_owned),
+            author: post.author().map(str::to_owned),
+            tripcode: post.tripcode().map(str::to_owned),
+            email: post.email().map(str::to_owned),
+            sage: post.sage(),
+            content: post.plaintext_content().map(str::to_owned),
+            posted_at: post.posted_at().assume_utc().unix_timestamp(),
+            images: images.iter().map(Uuid::to_string).collect(),
+        }
+    }
+}
+
+/// Pair `posts` up with their images, fetched in one go.
+async fn posts_json(board: &str, posts: &[Post], pool: &PgPool) -> Result<Vec<PostJson>, Error> {
+    let ids: Vec<i32> = posts.iter().map(Post::id).collect();
+    let mut images: HashMap<i32, Vec<Uuid>> = Post::images_for_posts(&ids, board, pool).await?;
+    Ok(posts
+        .iter()
+        .map(|p| PostJson::new(p, images.remove(&p.id()).unwrap_or_default()))
+        .collect())
+}
+
+#[get("/api/boards")]
+pub async fn boards(pool: &State<PgPool>) -> Result<Json<Vec<Board>>, Error> {
+    Ok(Json(Board::get_all(pool).await?))
+}
+
+/// The OPs of a board's threads, a page at a time like the board index.
+#[get("/api/<board>/threads?<page>")]
+pub async fn threads(
+    board: &str,
+    page: Option<i64>,
+    pool: &State<PgPool>,
+) -> Result<Json<Vec<PostJson>>, Error> {
+    let board = Board::get(board, pool).await?.ok_or(Error::NotFound)?;
+    let page = page.unwrap_or(1);
+    if page < 1 {
+        return Err(Error::NotFound);
+    }
+    let order = board.thread_order();
+    let per_page = Some(CONFIG.threads_per_page);
+    let ops = Post::threads_for_board(&board, &order, page - 1, per_page, pool).await?;
+    Ok(Json(posts_json(board.name(), &ops, pool).await?))
+}
+
+#[get("/api/<board>/<thread>", rank = 2)]
+pub async fn thread(
+    board: &str,
+    thread: i32,
+    pool: &State<PgPool>,
+) -> Result<Json<Vec<PostJson>>, Error> {
+    let posts = Post::for_thread(board, thread, pool).await?;
+    Ok(Json(posts_json(board, &posts, pool).await?))
+}
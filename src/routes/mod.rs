@@ -1,2 +1,15 @@
+use crate::config::CONFIG;
+use rocket::http::Cookie;
+
 pub mod admin;
+pub mod api;
 pub mod public;
+
+/// Build a cookie with the configured `SameSite` and `Secure` attributes.
+pub(crate) fn cookie(name: &'static str, value: String) -> Cookie<'static> {
+    Cookie::build(name, value)
+        .same_site(CONFIG.cookie_same_site.into())
+        .secure(CONFIG.cookie_secure)
+        .http_only(true)
+        .finish()
+}
@@ -1,23 +1,48 @@
+use super::admin;
+use crate::config::{Honeypot, CONFIG};
 use crate::errors::Error;
-use crate::models::{Board, Captcha, Image, NotBanned, Post, PostForm};
-use maud::{html, Markup};
+use crate::live::LiveThreads;
+use crate::models::{
+    clean_text, poster_hash, stored_ip, Board, Bytes, Captcha, CaptchaForm, CatalogSort, Image,
+    ImageMedia, ImageUse, MaintenanceWindow, ModPrivilege, NotBanned, Post, PostForm, Reply,
+    Report, ReportForm,
+};
+use crate::rate_limit::{RateLimiter, UploadSlots};
+use maud::{html, Markup, PreEscaped};
 use rocket::form::Form;
-use rocket::http::{Cookie, CookieJar};
-use rocket::response::Redirect;
-use rocket::{get, post, uri, State};
+use rocket::http::{Accept, ContentType, CookieJar, Status};
+use rocket::response::{
+    content::RawText,
+    stream::{Event, EventStream},
+    Redirect,
+};
+use rocket::serde::{json::Json, Serialize};
+use rocket::tokio::{select, sync::broadcast::error::RecvError};
+use rocket::{get, post, uri, Either, Shutdown, State};
+use sqlx::types::time::{OffsetDateTime, PrimitiveDateTime};
 use sqlx::types::Uuid;
 use sqlx::PgPool;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::future::Future;
 use std::net::IpAddr;
+use std::pin::Pin;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const CATALOG_PREVIEW_CHARS: usize = 150;
+const CATALOG_PAGE_SIZE: i64 = 100;
+const REPOST_LINKS: usize = 5;
 
 #[get("/")]
 pub async fn index(pool: &State<PgPool>) -> Result<Markup, Error> {
     Ok(html! {
-        (head())
+        (head("ruburu"))
         body {
+            (maintenance_banner(pool).await?)
             h1 { "Hello, ruburu!" }
             div {
                 @for board in Board::get_all(pool).await? {
-                    div { a href=(uri!(board(board.name())).to_string()) { (board.name()) } }
+                    div { a href=(uri!(board(board.name(), _, _, _)).to_string()) { (board.name()) } }
                 }
             }
         }
@@ -25,110 +50,738 @@ pub async fn index(pool: &State<PgPool>) -> Result<Markup, Error> {
     })
 }
 
+/// What scripts posting the form get back instead of a redirect.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(crate = "rocket::serde")]
+pub struct CreatedPost {
+    id: i32,
+    thread: i32,
+}
+
+/// Whether the client would rather have JSON than be redirected.
+fn wants_json(accept: Option<&Accept>) -> bool {
+    accept.map_or(false, |a| a.preferred().is_json())
+}
+
+/// Scripts posting the form can ask for the new post's id instead of being redirected,
+/// either with an `Accept: application/json` header or by posting to `/submit.json`.
 #[post("/submit", data = "<form>")]
+#[allow(clippy::too_many_arguments)]
 pub async fn create_post(
     form: Form<PostForm<'_>>,
     pool: &State<PgPool>,
     ip: IpAddr,
     _not_banned: NotBanned,
     cookies: &CookieJar<'_>,
-) -> Result<Redirect, Error> {
-    let captcha_id: Uuid = cookies
-        .get("captcha_id")
-        .map(|c| c.value())
-        .ok_or(Error::MissingOrInvalidCaptchaID)?
-        .parse()
-        .map_err(|_| Error::MissingOrInvalidCaptchaID)?;
-    if !Captcha::verify(captcha_id, form.captcha().unwrap(), pool).await? {
-        return Err(Error::MissingOrInvalidCaptchaID);
+    upload_slots: &State<UploadSlots>,
+    live: &State<LiveThreads>,
+    accept: Option<&Accept>,
+) -> Result<Either<Redirect, Json<CreatedPost>>, Error> {
+    let (board, created) = submit(&form, pool, ip, cookies, upload_slots, live).await?;
+    let (id, thread) = match created {
+        Some(created) => created,
+        None => {
+            return Ok(Either::Left(Redirect::to(uri!(board(
+                board.name(),
+                _,
+                _,
+                _
+            )))))
+        }
     };
+    if wants_json(accept) {
+        return Ok(Either::Right(Json(CreatedPost { id, thread })));
+    }
+    Ok(Either::Left(Redirect::to(uri!(thread(
+        board.name(),
+        thread,
+        _
+    )))))
+}
 
-    let image = if let Some(file) = &form.image {
-        Some(Image::from_buf(file, pool).await?)
-    } else {
+/// `/submit` for clients that can't set headers. A post dropped by the honeypot
+/// gets `202 Accepted` with no body, since there's no post to point to.
+#[post("/submit.json", data = "<form>")]
+pub async fn create_post_json(
+    form: Form<PostForm<'_>>,
+    pool: &State<PgPool>,
+    ip: IpAddr,
+    _not_banned: NotBanned,
+    cookies: &CookieJar<'_>,
+    upload_slots: &State<UploadSlots>,
+    live: &State<LiveThreads>,
+) -> Result<Either<Json<CreatedPost>, Status>, Error> {
+    let (_, created) = submit(&form, pool, ip, cookies, upload_slots, live).await?;
+    Ok(match created {
+        Some((id, thread)) => Either::Left(Json(CreatedPost { id, thread })),
+        None => Either::Right(Status::Accepted),
+    })
+}
+
+/// Post the form like `/submit` does, but answer with the new post rendered on its own
+/// so that scripts can add it to the page without reloading the thread.
+#[post("/api/submit", data = "<form>")]
+pub async fn quick_reply(
+    form: Form<PostForm<'_>>,
+    pool: &State<PgPool>,
+    ip: IpAddr,
+    _not_banned: NotBanned,
+    cookies: &CookieJar<'_>,
+    upload_slots: &State<UploadSlots>,
+    live: &State<LiveThreads>,
+) -> Result<Markup, Error> {
+    let (board, created) = submit(&form, pool, ip, cookies, upload_slots, live).await?;
+    // A post dropped by the honeypot should look like it went through.
+    let (id, _) = match created {
+        Some(created) => created,
+        None => return Ok(html! {}),
+    };
+    let post = Post::get(board.name(), id, pool)
+        .await?
+        .ok_or(Error::NotFound)?;
+    let viewer = Viewer::new(false, cookies);
+    let images = prefetch_images(&board, [&post], pool).await?;
+    post_body(
+        &post,
+        &board,
+        Vec::new(),
+        &images,
+        false,
+        false,
+        &viewer,
+        pool,
+    )
+    .await
+}
+
+/// How much longer someone who last posted at `last` has to wait before posting again.
+fn cooldown_left(
+    last: Option<PrimitiveDateTime>,
+    cooldown: Duration,
+    now: OffsetDateTime,
+) -> Option<Duration> {
+    let elapsed = now - last?.assume_utc();
+    let elapsed = Duration::try_from(elapsed).unwrap_or_default();
+    cooldown.checked_sub(elapsed).filter(|wait| !wait.is_zero())
+}
+
+/// Check and store a submitted post, returning its board along with the new post's id and thread.
+/// There's no post if the honeypot is set to drop whatever it catches.
+/// Replies are also sent to everyone following their thread live.
+async fn submit(
+    form: &PostForm<'_>,
+    pool: &PgPool,
+    ip: IpAddr,
+    cookies: &CookieJar<'_>,
+    upload_slots: &UploadSlots,
+    live: &LiveThreads,
+) -> Result<(Board, Option<(i32, i32)>), Error> {
+    MaintenanceWindow::ensure_writable(pool).await?;
+    let board = Board::get(form.board.as_ref(), pool)
+        .await?
+        .ok_or(Error::NotFound)?;
+    if form.honeypot.is_some() {
+        match CONFIG.honeypot {
+            Honeypot::Off => {}
+            Honeypot::Reject => return Err(Error::Rejected),
+            Honeypot::Drop => return Ok((board, None)),
+        }
+    }
+    // Cooldowns go by posts that were actually made, so a rejected post can be fixed
+    // and sent again right away. They're checked before the captcha so that waiting
+    // one out doesn't cost a new captcha.
+    let now = OffsetDateTime::now_utc();
+    let last_post = Post::last_post_at_for_ip(stored_ip(ip), false, pool).await?;
+    if let Some(wait) = cooldown_left(last_post, CONFIG.post_cooldown, now) {
+        return Err(Error::Flood(wait));
+    }
+    // Starting a thread counts as a post as well, on top of its own longer cooldown.
+    if form.thread.is_none() {
+        let last_thread = Post::last_post_at_for_ip(stored_ip(ip), true, pool).await?;
+        if let Some(wait) = cooldown_left(last_thread, CONFIG.thread_cooldown, now) {
+            return Err(Error::Flood(wait));
+        }
+    }
+    if board.captcha_required(form.thread) && !has_captcha_pass(cookies) {
+        let captcha_id = captcha_id(cookies)?;
+        let answer = form.captcha().ok_or(Error::MissingOrInvalidCaptchaID)?;
+        Captcha::ensure_not_locked_out(stored_ip(ip), pool).await?;
+        if !Captcha::verify(captcha_id, answer, pool).await? {
+            Captcha::record_failure(stored_ip(ip), pool).await?;
+            return Err(Error::MissingOrInvalidCaptchaID);
+        };
+        Captcha::clear_failures(stored_ip(ip), pool).await?;
+        grant_captcha_pass(cookies);
+    }
+
+    let title = clean_text(form.title.as_deref())?;
+    let author = clean_text(form.author.as_deref())?;
+    let content = clean_text(form.content.as_deref())?;
+    let email = if board.hide_email() {
         None
+    } else {
+        form.email.as_deref()
     };
-    let id = if let Some(thread) = form.thread {
-        Post::create(
-            form.board.as_ref(),
+    let files: Vec<&Bytes> = form.images.iter().flatten().collect();
+    if !files.is_empty() && board.text_only() {
+        return Err(Error::ImagesDisabled);
+    }
+    if files.len() > CONFIG.max_images_per_post {
+        return Err(Error::TooManyImages);
+    }
+    let mut images = Vec::with_capacity(files.len());
+    if !files.is_empty() {
+        let _slot = upload_slots
+            .acquire(ip, CONFIG.max_uploads_per_ip)
+            .ok_or(Error::TooFast)?;
+        for file in files {
+            images.push(Image::from_buf(file, &board, pool).await?);
+        }
+    }
+    let poster = poster_hash(&poster_token(cookies));
+    let (id, thread) = if let Some(thread) = form.thread {
+        let id = Post::create(
+            &board,
             thread,
-            form.title.as_deref(),
-            form.author.as_deref(),
-            form.email.as_deref(),
+            title.as_deref(),
+            author.as_deref(),
+            email,
             form.sage,
-            form.content.as_deref(),
-            ip.into(),
-            image,
+            content.as_deref(),
+            stored_ip(ip),
+            &poster,
+            images,
             pool,
         )
         .await?;
-        thread
+        if let Some(post) = Post::get(board.name(), id, pool).await? {
+            // What's sent out is shared by every follower, so it can't be marked as anyone's own.
+            let viewer = Viewer {
+                mod_tools: false,
+                poster: None,
+            };
+            let images = prefetch_images(&board, [&post], pool).await?;
+            let html = post_body(
+                &post,
+                &board,
+                Vec::new(),
+                &images,
+                false,
+                false,
+                &viewer,
+                pool,
+            )
+            .await?;
+            live.publish(board.name(), thread, html.into_string());
+        }
+        (id, thread)
     } else {
-        Post::create_thread(
-            form.board.as_ref(),
-            form.title.as_deref(),
-            form.author.as_deref(),
-            form.email.as_deref(),
+        let id = Post::create_thread(
+            &board,
+            title.as_deref(),
+            author.as_deref(),
+            email,
             form.sage,
-            form.content.as_deref(),
-            ip.into(),
-            {
-                if let Some(image) = image {
-                    image
-                } else {
-                    return Err(Error::MissingImage);
-                }
-            },
+            content.as_deref(),
+            stored_ip(ip),
+            &poster,
+            images,
             pool,
         )
-        .await?
+        .await?;
+        (id, id)
     };
-    Ok(Redirect::to(uri!(thread(&*form.board, id))))
+    Ok((board, Some((id, thread))))
+}
+
+/// Server-sent events carrying each reply posted to a thread from now on,
+/// rendered the same way as on the thread page.
+/// Ranked below `api::thread` and `archived_thread`, whose paths it overlaps.
+#[get("/<board>/<thread>/live", rank = 5)]
+pub async fn live(
+    board: &str,
+    thread: i32,
+    pool: &State<PgPool>,
+    live: &State<LiveThreads>,
+    mut shutdown: Shutdown,
+) -> Result<EventStream![], Error> {
+    let op = Post::get(board, thread, pool)
+        .await?
+        .filter(|op| op.id() == op.thread())
+        .ok_or(Error::NotFound)?;
+    let mut posts = live
+        .subscribe(op.board(), op.id())
+        .ok_or(Error::TooManyFollowers)?;
+    // The receiver goes away with the stream once the client disconnects,
+    // which is what lets publishing notice nobody's left.
+    Ok(EventStream! {
+        loop {
+            let post = select! {
+                post = posts.recv() => match post {
+                    Ok(post) => post,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+                _ = &mut shutdown => break,
+            };
+            yield Event::data(post).event("post");
+        }
+    })
 }
 
-#[get("/<board>", rank = 3)]
+#[post("/report", data = "<form>")]
+pub async fn report(
+    form: Form<ReportForm<'_>>,
+    pool: &State<PgPool>,
+    ip: IpAddr,
+    _not_banned: NotBanned,
+) -> Result<Redirect, Error> {
+    let post = Post::get(&form.board, form.post, pool)
+        .await?
+        .ok_or(Error::NotFound)?;
+    let reason = clean_text(Some(form.reason.as_ref()))?.ok_or(Error::InvisibleCharacters)?;
+    Report::create(post.board(), post.id(), &reason, stored_ip(ip), pool).await?;
+    Ok(Redirect::to(format!(
+        "{}#{}",
+        uri!(thread(post.board(), post.thread(), _)),
+        post.id()
+    )))
+}
+
+/// Check the captcha answer typed into the post form before it's submitted.
+/// The captcha stays valid for the post itself, but wrong answers still count towards the lockout.
+#[post("/captcha/check", data = "<form>")]
+pub async fn check_captcha(
+    form: Form<CaptchaForm<'_>>,
+    pool: &State<PgPool>,
+    ip: IpAddr,
+    cookies: &CookieJar<'_>,
+    limiter: &State<Box<dyn RateLimiter>>,
+) -> Result<Status, Error> {
+    let captcha_id = captcha_id(cookies)?;
+    Captcha::ensure_not_locked_out(stored_ip(ip), pool).await?;
+    if limiter
+        .hit(ip, "captcha_check", CONFIG.captcha_check_cooldown)
+        .await?
+        .is_some()
+    {
+        return Err(Error::TooFast);
+    }
+    if Captcha::peek(captcha_id, &form.captcha, pool).await? {
+        Ok(Status::NoContent)
+    } else {
+        Captcha::record_failure(stored_ip(ip), pool).await?;
+        Err(Error::MissingOrInvalidCaptchaID)
+    }
+}
+
+/// Who a page is being rendered for.
+struct Viewer {
+    /// Whether to show links for moderating posts.
+    mod_tools: bool,
+    /// Hash of the viewer's poster token if they have one, see [`poster_token`].
+    poster: Option<Vec<u8>>,
+}
+
+impl Viewer {
+    fn new(mod_tools: bool, cookies: &CookieJar<'_>) -> Self {
+        let poster = cookies
+            .get_private(POSTER_COOKIE)
+            .map(|c| poster_hash(c.value()));
+        Self { mod_tools, poster }
+    }
+
+    /// Whether a post made with the poster token hash `poster` is the viewer's.
+    fn owns(&self, poster: Option<&[u8]>) -> bool {
+        matches!((&self.poster, poster), (Some(own), Some(poster)) if own.as_slice() == poster)
+    }
+}
+
+const POSTER_COOKIE: &str = "poster_token";
+
+/// Get the random token that ties a poster's posts together, handing out a new one
+/// on their first post. Only its hash is stored, and the cookie is encrypted.
+fn poster_token(cookies: &CookieJar<'_>) -> String {
+    if let Some(token) = cookies.get_private(POSTER_COOKIE) {
+        return token.value().to_owned();
+    }
+    let token = uuid::Uuid::new_v4().to_string();
+    let mut cookie = super::cookie(POSTER_COOKIE, token.clone());
+    cookie.set_max_age(rocket::time::Duration::days(365));
+    cookies.add_private(cookie);
+    token
+}
+
+/// After solving a captcha, let the poster skip them for `CAPTCHA_PASS_SECS`, if set.
+/// The pass isn't renewed by posting with it, so it always runs out.
+fn grant_captcha_pass(cookies: &CookieJar<'_>) {
+    if let Some(ttl) = CONFIG.captcha_pass {
+        let expires = SystemTime::now() + ttl;
+        let expires = expires
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        cookies.add_private(super::cookie("captcha_pass", expires.to_string()));
+    }
+}
+
+fn has_captcha_pass(cookies: &CookieJar<'_>) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    cookies
+        .get_private("captcha_pass")
+        .and_then(|c| c.value().parse::<u64>().ok())
+        .map_or(false, |expires| expires > now)
+}
+
+fn captcha_id(cookies: &CookieJar<'_>) -> Result<Uuid, Error> {
+    cookies
+        .get("captcha_id")
+        .map(|c| c.value())
+        .ok_or(Error::MissingOrInvalidCaptchaID)?
+        .parse()
+        .map_err(|_| Error::MissingOrInvalidCaptchaID)
+}
+
+/// Mods can pass `?preview=1` along with `bump_limit` and/or `sage_stops_bump`
+/// to see how the board would be ordered under those settings.
+#[get("/<board>?<preview>&<bump_limit>&<sage_stops_bump>", rank = 3)]
 pub async fn board(
     board: &str,
+    preview: Option<&str>,
+    bump_limit: Option<i32>,
+    sage_stops_bump: Option<bool>,
+    privilege: Option<ModPrivilege>,
     pool: &State<PgPool>,
     cookies: &CookieJar<'_>,
+) -> Result<Markup, Error> {
+    let preview = preview.is_some() && privilege.is_some();
+    render_board(
+        board,
+        1,
+        preview,
+        bump_limit,
+        sage_stops_bump,
+        &Viewer::new(privilege.is_some(), cookies),
+        pool,
+        cookies,
+    )
+    .await
+}
+
+/// Pages are numbered from 1, which is the same as the board index.
+#[get(
+    "/<board>/page/<page>?<preview>&<bump_limit>&<sage_stops_bump>",
+    rank = 3
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn board_page(
+    board: &str,
+    page: i64,
+    preview: Option<&str>,
+    bump_limit: Option<i32>,
+    sage_stops_bump: Option<bool>,
+    privilege: Option<ModPrivilege>,
+    pool: &State<PgPool>,
+    cookies: &CookieJar<'_>,
+) -> Result<Markup, Error> {
+    if page < 1 {
+        return Err(Error::NotFound);
+    }
+    let preview = preview.is_some() && privilege.is_some();
+    render_board(
+        board,
+        page,
+        preview,
+        bump_limit,
+        sage_stops_bump,
+        &Viewer::new(privilege.is_some(), cookies),
+        pool,
+        cookies,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn render_board(
+    board: &str,
+    page: i64,
+    preview: bool,
+    bump_limit: Option<i32>,
+    sage_stops_bump: Option<bool>,
+    viewer: &Viewer,
+    pool: &PgPool,
+    cookies: &CookieJar<'_>,
 ) -> Result<Markup, Error> {
     let board = Board::get(board, pool).await?.ok_or(Error::NotFound)?;
-    let captcha = Captcha::new(pool).await?;
-    cookies.add(Cookie::new("captcha_id", captcha.id().to_string()));
+    let mut order = board.thread_order();
+    if preview {
+        order.bump_limit = bump_limit.or(order.bump_limit);
+        order.sage_stops_bump = sage_stops_bump.unwrap_or(order.sage_stops_bump);
+    }
+    let per_page = CONFIG.threads_per_page;
+    let threads = Post::threads_for_board(&board, &order, page - 1, Some(per_page), pool).await?;
+    if threads.is_empty() && page > 1 {
+        return Err(Error::NotFound);
+    }
+    let ids: Vec<i32> = threads.iter().map(Post::id).collect();
+    let sizes = Post::thread_sizes(&ids, board.name(), pool).await?;
+    let mut tails = Post::last_replies(&ids, board.name(), CONFIG.preview_replies, pool).await?;
+    let shown = || threads.iter().chain(tails.values().flatten());
+    let mut replies = prefetch_replies(&board, shown(), pool).await?;
+    let images = prefetch_images(&board, shown(), pool).await?;
+    let pages = (Post::thread_count(board.name(), pool).await? + per_page - 1) / per_page;
+    let captcha = issue_captcha(&board, None, pool, cookies).await?;
     Ok(html! {
-        (head())
+        (board_head(&format!("/{}/ - {}", board.name(), board.title()), Some(&board)))
         body {
+            (maintenance_banner(pool).await?)
             h1 { (board.name()) }
             h2 { (board.title()) }
-            (post_form(board.name(), None,Some(captcha.base64image())))
-            @for head in Post::threads_for_board(board.name(), pool).await? {
-                (post_body(&head, pool).await?)
+            @if let Some(description) = board.description_html() {
+                .description { (PreEscaped(description)) }
+            }
+            (post_form(&board, None, None, captcha.as_ref().map(Captcha::base64image)))
+            @if preview {
+                .preview { (format!("Previewing thread order with {order:?}")) }
+            }
+            @for head in threads {
+                @let size = sizes.get(&head.id()).copied().unwrap_or(0);
+                @let tail = tails.remove(&head.id()).unwrap_or_default();
+                .thread {
+                    @let head_replies = replies.remove(&head.id()).unwrap_or_default();
+                    @let bumpless = bump_limit_reached(&board, size);
+                    (post_body(&head, &board, head_replies, &images, bumpless, true, viewer, pool).await?)
+                    @let omitted = size - 1 - tail.len() as i64;
+                    @if omitted > 0 {
+                        .omitted {
+                            a href=(uri!(thread(head.board(), head.id(), _)).to_string()) {
+                                (format!("{omitted} posts omitted"))
+                            }
+                        }
+                    }
+                    @for post in &tail {
+                        @let post_replies = replies.remove(&post.id()).unwrap_or_default();
+                        (post_body(post, &board, post_replies, &images, false, true, viewer, pool).await?)
+                    }
+                }
+            }
+            .pages {
+                @if page == 2 {
+                    a href=(uri!(board(board.name(), _, _, _)).to_string()) { "Previous" }
+                } @else if page > 2 {
+                    a href=(uri!(board_page(board.name(), page - 1, _, _, _)).to_string()) { "Previous" }
+                }
+                @if page < pages {
+                    a href=(uri!(board_page(board.name(), page + 1, _, _, _)).to_string()) { "Next" }
+                }
             }
         }
         (footer())
     })
 }
 
-#[get("/<board>/<thread>", rank = 3)]
+/// `?reply_to=<id>` starts the reply form off quoting that post, for browsers without JS.
+#[get("/<board>/<thread>?<reply_to>", rank = 3)]
 pub async fn thread(
     board: &str,
     thread: i32,
+    reply_to: Option<i32>,
+    privilege: Option<ModPrivilege>,
     pool: &State<PgPool>,
     cookies: &CookieJar<'_>,
 ) -> Result<Markup, Error> {
     let board = Board::get(board, pool).await?.ok_or(Error::NotFound)?;
     let posts = Post::for_thread(board.name(), thread, pool).await?;
-    let captcha = Captcha::new(pool).await?;
-    cookies.add(Cookie::new("captcha_id", captcha.id().to_string()));
+    if let Some(id) = reply_to {
+        if !posts.iter().any(|p| p.id() == id) {
+            return Err(Error::NotFound);
+        }
+    }
+    render_thread(
+        &board,
+        thread,
+        &posts,
+        reply_to,
+        &Viewer::new(privilege.is_some(), cookies),
+        pool,
+        cookies,
+    )
+    .await
+}
+
+#[get("/<board>/archive", rank = 2)]
+pub async fn archive(board: &str, pool: &State<PgPool>) -> Result<Markup, Error> {
+    let board = Board::get(board, pool).await?.ok_or(Error::NotFound)?;
+    let threads = Post::archived_threads(board.name(), pool).await?;
+    Ok(html! {
+        (board_head(&format!("/{}/ - Archive", board.name()), Some(&board)))
+        body {
+            h1 { (board.name()) }
+            h2 { (board.title()) }
+            table.archive {
+                tbody {
+                    @for op in &threads {
+                        tr {
+                            td {
+                                a href=(uri!(archived_thread(op.board(), op.id())).to_string()) { (op.id()) }
+                            }
+                            td { (op.posted_at().format("%Y-%m-%d %H:%M")) }
+                            td { (op.title().unwrap_or_default()) }
+                            td { (truncate(op.plaintext_content().unwrap_or_default(), CATALOG_PREVIEW_CHARS)) }
+                        }
+                    }
+                }
+            }
+        }
+        (footer())
+    })
+}
+
+/// Ranked below `api::thread`, which also matches `/api/archive/<thread>`.
+#[get("/<board>/archive/<thread>", rank = 4)]
+pub async fn archived_thread(
+    board: &str,
+    thread: i32,
+    privilege: Option<ModPrivilege>,
+    pool: &State<PgPool>,
+    cookies: &CookieJar<'_>,
+) -> Result<Markup, Error> {
+    let board = Board::get(board, pool).await?.ok_or(Error::NotFound)?;
+    let posts = Post::archived_thread(board.name(), thread, pool).await?;
+    render_thread(
+        &board,
+        thread,
+        &posts,
+        None,
+        &Viewer::new(privilege.is_some(), cookies),
+        pool,
+        cookies,
+    )
+    .await
+}
+
+/// Render `thread` with its `posts`, and a reply form unless it's locked or archived.
+async fn render_thread(
+    board: &Board,
+    thread: i32,
+    posts: &[Post],
+    reply_to: Option<i32>,
+    viewer: &Viewer,
+    pool: &PgPool,
+    cookies: &CookieJar<'_>,
+) -> Result<Markup, Error> {
+    let op = posts.iter().find(|p| p.id() == thread);
+    let archived = op.map_or(false, Post::archived);
+    let locked = op.map_or(false, Post::locked);
+    let mut replies = prefetch_replies(board, posts, pool).await?;
+    let images = prefetch_images(board, posts, pool).await?;
+    let captcha = if archived || locked {
+        None
+    } else {
+        issue_captcha(board, Some(thread), pool, cookies).await?
+    };
     Ok(html! {
-        (head())
+        (board_head(&thread_title(board, posts), Some(board)))
         body {
+            (maintenance_banner(pool).await?)
             h1 { (board.name()) }
             h2 { (board.title()) }
-            (post_form(board.name(), Some(thread),  Some(captcha.base64image())))
+            @if archived {
+                .locked-notice { "This thread is archived, no more replies can be posted" }
+            } @else if locked {
+                .locked-notice { "This thread is locked, no more replies can be posted" }
+            } @else {
+                (post_form(board, Some(thread), reply_to, captcha.as_ref().map(Captcha::base64image)))
+            }
             .thread {
                 @for post in posts {
-                    (post_body(&post, pool).await?)
+                    @let replies = replies.remove(&post.id()).unwrap_or_default();
+                    @let bumpless = post.id() == thread && bump_limit_reached(board, posts.len() as i64);
+                    (post_body(post, board, replies, &images, bumpless, false, viewer, pool).await?)
+                }
+            }
+        }
+        (footer())
+    })
+}
+
+/// `?q=` narrows the catalog down to threads whose subject or OP mention it,
+/// and `?sort=` picks between bump order, newest first and most replies.
+#[get("/<board>/catalog?<q>&<sort>&<page>", rank = 2)]
+pub async fn catalog(
+    board: &str,
+    q: Option<&str>,
+    sort: Option<CatalogSort>,
+    page: Option<i64>,
+    pool: &State<PgPool>,
+) -> Result<Markup, Error> {
+    let board = Board::get(board, pool).await?.ok_or(Error::NotFound)?;
+    let q = q.map(str::trim).filter(|q| !q.is_empty());
+    let sort = sort.unwrap_or_default();
+    let page = page.unwrap_or(1);
+    if page < 1 {
+        return Err(Error::NotFound);
+    }
+    let count = Post::catalog_count(&board, q, pool).await?;
+    let pages = ((count + CATALOG_PAGE_SIZE - 1) / CATALOG_PAGE_SIZE).max(1);
+    if page > pages {
+        return Err(Error::NotFound);
+    }
+    let threads =
+        Post::catalog_for_board(&board, q, sort, page - 1, CATALOG_PAGE_SIZE, pool).await?;
+    Ok(html! {
+        (board_head(&format!("/{}/ - Catalog", board.name()), Some(&board)))
+        body {
+            h1 { (board.name()) }
+            h2 { (board.title()) }
+            form.search action=(uri!(catalog(board.name(), _, _, _)).to_string()) method="get" {
+                input type="search" name="q" value=(q.unwrap_or_default());
+                input type="hidden" name="sort" value=(sort.as_str());
+                input type="submit" value="Filter";
+            }
+            .sort {
+                "Sort by: "
+                @for option in CatalogSort::ALL {
+                    @if option == sort {
+                        b { (option.as_str()) }
+                    } @else {
+                        a href=(uri!(catalog(board.name(), q, Some(option), _)).to_string()) { (option.as_str()) }
+                    }
+                }
+            }
+            @if q.is_some() && threads.is_empty() {
+                p { "No threads found" }
+            }
+            .catalog {
+                @for thread in &threads {
+                    @let op = thread.op();
+                    a.thread href=(uri!(thread(op.board(), op.id(), _)).to_string()) {
+                        @if let Some(img) = op.image() {
+                            img src=(Image::thumb_src(*img, pool).await?);
+                        }
+                        .counts {
+                            @if op.is_sticky() { "📌 " }
+                            @if op.locked() { "🔒 " }
+                            (format!("R: {} / I: {}", thread.replies(), thread.images()))
+                        }
+                        @if let Some(title) = op.title() {
+                            .title { (title) }
+                        }
+                        @if let Some(content) = op.plaintext_content() {
+                            .text { (truncate(content, CATALOG_PREVIEW_CHARS)) }
+                        }
+                    }
+                }
+            }
+            .pages {
+                @if page > 1 {
+                    a href=(uri!(catalog(board.name(), q, Some(sort), Some(page - 1))).to_string()) { "Previous" }
+                }
+                @if page < pages {
+                    a href=(uri!(catalog(board.name(), q, Some(sort), Some(page + 1))).to_string()) { "Next" }
                 }
             }
         }
@@ -136,16 +789,189 @@ pub async fn thread(
     })
 }
 
-fn head() -> Markup {
+/// Posts on a board matching `q`. An empty or missing query just shows the search box.
+#[get("/<board>/search?<q>", rank = 2)]
+pub async fn search(
+    board: &str,
+    q: Option<&str>,
+    pool: &State<PgPool>,
+    cookies: &CookieJar<'_>,
+) -> Result<Markup, Error> {
+    let board = Board::get(board, pool).await?.ok_or(Error::NotFound)?;
+    let q = q.map(str::trim).unwrap_or_default();
+    let posts = Post::search(board.name(), q, pool).await?;
+    let mut replies = prefetch_replies(&board, &posts, pool).await?;
+    let images = prefetch_images(&board, &posts, pool).await?;
+    let viewer = Viewer::new(false, cookies);
+    Ok(html! {
+        (board_head(&format!("/{}/ - Search", board.name()), Some(&board)))
+        body {
+            h1 { (board.name()) }
+            h2 { (board.title()) }
+            form.search action=(uri!(search(board.name(), _)).to_string()) method="get" {
+                input type="search" name="q" value=(q);
+                input type="submit" value="Search";
+            }
+            @if !q.is_empty() && posts.is_empty() {
+                p { "No posts found" }
+            }
+            @for post in &posts {
+                @let replies = replies.remove(&post.id()).unwrap_or_default();
+                (post_body(post, &board, replies, &images, false, true, &viewer, pool).await?)
+            }
+        }
+        (footer())
+    })
+}
+
+const FEED_ITEMS: i64 = 20;
+
+/// RSS feed of the newest posts on a board. Links are made absolute with `SITE_URL`.
+#[get("/<board>/feed", rank = 2)]
+pub async fn board_feed(board: &str, pool: &State<PgPool>) -> Result<(ContentType, String), Error> {
+    let board = Board::get(board, pool).await?.ok_or(Error::NotFound)?;
+    let site = CONFIG.site_url.trim_end_matches('/');
+    let mut items = String::new();
+    for post in Post::recent_for_board(board.name(), FEED_ITEMS, pool).await? {
+        let link = format!(
+            "{site}{}#{}",
+            uri!(thread(post.board(), post.thread(), _)),
+            post.id()
+        );
+        let title = match post.title() {
+            Some(title) => format!("{title} (>>{})", post.id()),
+            None => format!(">>{}", post.id()),
+        };
+        let description = post
+            .plaintext_content()
+            .map_or(Cow::Borrowed(""), |c| truncate(c, CONFIG.preview_max_chars));
+        let date = post
+            .posted_at()
+            .assume_utc()
+            .format("%a, %d %b %Y %H:%M:%S +0000");
+        items.push_str(&format!(
+            "<item><title>{}</title><link>{}</link><guid>{}</guid>\
+            <pubDate>{date}</pubDate><description>{}</description></item>",
+            xml_escape(&title),
+            xml_escape(&link),
+            xml_escape(&link),
+            xml_escape(&description)
+        ));
+    }
+    let feed = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?><rss version=\"2.0\"><channel>\
+        <title>/{}/ - {}</title><link>{}</link><description>Newest posts on /{}/</description>\
+        {items}</channel></rss>",
+        xml_escape(board.name()),
+        xml_escape(board.title()),
+        xml_escape(&format!("{site}{}", uri!(board(board.name(), _, _, _)))),
+        xml_escape(board.name())
+    );
+    Ok((ContentType::new("application", "rss+xml"), feed))
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[get("/<board>/p/<id>", rank = 3)]
+pub async fn permalink(board: &str, id: i32, pool: &State<PgPool>) -> Result<Redirect, Error> {
+    let post = Post::get(board, id, pool).await?.ok_or(Error::NotFound)?;
+    Ok(Redirect::found(format!(
+        "{}#{}",
+        uri!(thread(post.board(), post.thread(), _)),
+        post.id()
+    )))
+}
+
+/// Generate a captcha for the post form if the board wants one for posts to `thread`.
+async fn issue_captcha(
+    board: &Board,
+    thread: Option<i32>,
+    pool: &PgPool,
+    cookies: &CookieJar<'_>,
+) -> Result<Option<Captcha>, Error> {
+    if !board.captcha_required(thread) || has_captcha_pass(cookies) {
+        return Ok(None);
+    }
+    let captcha = Captcha::new(pool).await?;
+    cookies.add(super::cookie("captcha_id", captcha.id().to_string()));
+    Ok(Some(captcha))
+}
+
+pub(crate) fn head(title: &str) -> Markup {
+    board_head(title, None)
+}
+
+/// `head`, with the board's own stylesheet if it has one.
+fn board_head(title: &str, board: Option<&Board>) -> Markup {
     html! {
         head {
+            title { (title) }
             link rel="stylesheet" href="/static/style.css";
+            @if let Some(css) = board.and_then(Board::custom_css) {
+                style { (PreEscaped(css)) }
+            }
             script src="/static/script.js" {}
         }
     }
 }
 
-fn footer() -> Markup {
+/// The post's text as it was submitted.
+#[get("/<board>/post/<id>/raw", rank = 3)]
+pub async fn raw_post(
+    board: &str,
+    id: i32,
+    pool: &State<PgPool>,
+) -> Result<RawText<String>, Error> {
+    let post = Post::get(board, id, pool).await?.ok_or(Error::NotFound)?;
+    let content = post.plaintext_content().ok_or(Error::NotFound)?;
+    Ok(RawText(content.to_owned()))
+}
+
+/// `/b/ - Subject`, falling back to the start of the OP's text, then to the board title.
+fn thread_title(board: &Board, posts: &[Post]) -> String {
+    let op = posts.iter().find(|p| p.id() == p.thread());
+    let subject = op
+        .and_then(|op| op.title().or_else(|| op.plaintext_content()))
+        .unwrap_or_else(|| board.title());
+    page_title(board.name(), subject, CONFIG.title_max_chars)
+}
+
+/// Title a page of `board` with `subject`, cut down to `max` characters.
+fn page_title(board: &str, subject: &str, max: usize) -> String {
+    format!("/{board}/ - {}", truncate(subject, max))
+}
+
+fn truncate(s: &str, max: usize) -> Cow<'_, str> {
+    match s.char_indices().nth(max) {
+        Some((end, _)) => Cow::Owned(format!("{}…", s[..end].trim_end())),
+        None => Cow::Borrowed(s),
+    }
+}
+
+async fn maintenance_banner(pool: &PgPool) -> Result<Markup, Error> {
+    Ok(html! {
+        @if let Some(window) = MaintenanceWindow::upcoming(pool).await? {
+            .maintenance {
+                @if window.active() {
+                    "Posting is disabled for maintenance until "
+                } @else {
+                    "Posting will be disabled for maintenance from "
+                    (window.starts_at().format("%Y-%m-%d %H:%M"))
+                    " until "
+                }
+                (window.ends_at().format("%Y-%m-%d %H:%M"))
+            }
+        }
+    })
+}
+
+pub(crate) fn footer() -> Markup {
     html! {
         footer {
             script { "ready();" }
@@ -153,7 +979,94 @@ fn footer() -> Markup {
     }
 }
 
-async fn post_body(post: &Post, pool: &PgPool) -> Result<Markup, Error> {
+/// The `accept` hint on `board`'s post form file input.
+fn upload_accept(board: &Board) -> String {
+    let mut types: Vec<String> = CONFIG
+        .allowed_image_formats
+        .iter()
+        .filter_map(|f| ContentType::from_extension(f.extensions_str()[0]))
+        .map(|ct| ct.to_string())
+        .collect();
+    if CONFIG.ffmpeg.is_some() {
+        types.extend(["video/webm".to_string(), "video/mp4".to_string()]);
+    }
+    if let Some(allowed) = board.allowed_mime_types() {
+        types.retain(|t| allowed.contains(t));
+    }
+    types.join(", ")
+}
+
+/// Get the replies to all of `posts` at once if the board shows backlinks,
+/// so that rendering a page doesn't need a query per post.
+async fn prefetch_replies<'p>(
+    board: &Board,
+    posts: impl IntoIterator<Item = &'p Post>,
+    pool: &PgPool,
+) -> Result<HashMap<i32, Vec<Reply>>, Error> {
+    if !board.backlinks() {
+        return Ok(HashMap::new());
+    }
+    let ids: Vec<i32> = posts.into_iter().map(Post::id).collect();
+    Ok(Post::replies_for_posts(&ids, board.name(), pool).await?)
+}
+
+/// The images attached to a page's posts, along with what rendering them needs.
+struct PageImages {
+    attached: HashMap<i32, Vec<Uuid>>,
+    media: HashMap<Uuid, ImageMedia>,
+    uses: HashMap<Uuid, Vec<ImageUse>>,
+}
+
+/// Get the images of all of `posts` at once, and their earlier uses if the board
+/// points out reposts, so that rendering a page doesn't need queries per post or image.
+async fn prefetch_images<'p>(
+    board: &Board,
+    posts: impl IntoIterator<Item = &'p Post>,
+    pool: &PgPool,
+) -> Result<PageImages, Error> {
+    let ids: Vec<i32> = posts
+        .into_iter()
+        .filter(|p| p.image().is_some())
+        .map(Post::id)
+        .collect();
+    let attached = Post::images_for_posts(&ids, board.name(), pool).await?;
+    let hashes: Vec<Uuid> = attached.values().flatten().copied().collect();
+    let media = Image::media_for(&hashes, pool).await?;
+    let uses = if board.repost_notice() {
+        Image::uses_of(&hashes, pool).await?
+    } else {
+        HashMap::new()
+    };
+    Ok(PageImages {
+        attached,
+        media,
+        uses,
+    })
+}
+
+fn bump_limit_reached(board: &Board, thread_size: i64) -> bool {
+    board
+        .bump_limit()
+        .map_or(false, |limit| thread_size >= limit.into())
+}
+
+/// Render a post along with links to its `replies` and its prefetched `images`,
+/// flagged if its thread hit the bump limit.
+/// In a `listing` of posts from several threads, long posts are cut down to a preview
+/// linking to the full text, and ids carry the board prefix if `PREFIX_POST_IDS` is set.
+/// Posts made by the `viewer` are marked as theirs, and mods get links for moderating the post.
+#[allow(clippy::too_many_arguments)]
+async fn post_body(
+    post: &Post,
+    board: &Board,
+    replies: Vec<Reply>,
+    images: &PageImages,
+    bump_limit_reached: bool,
+    listing: bool,
+    viewer: &Viewer,
+    pool: &PgPool,
+) -> Result<Markup, Error> {
+    let overflow = listing && overflows(post.plaintext_content(), CONFIG.preview_max_chars);
     Ok(html! {
         .post id=(post.id()) {
             .info {
@@ -166,44 +1079,183 @@ async fn post_body(post: &Post, pool: &PgPool) -> Result<Markup, Error> {
                 @if let Some(author) = post.author() {
                     .author { (author) }
                 }
+                @if let Some(tripcode) = post.tripcode() {
+                    .tripcode { "!" (tripcode) }
+                }
                 @if let Some(email) = post.email() {
                     .email { (email) }
                 }
+                @if viewer.owns(post.poster()) {
+                    .you { "(You)" }
+                }
+                @if post.is_sticky() {
+                    .sticky title="Sticky" { "📌" }
+                }
+                @if post.locked() {
+                    .locked title="Locked" { "🔒" }
+                }
+                @if bump_limit_reached {
+                    .bump-limit { "Bump limit reached" }
+                }
                 .id {
-                    a href=(format!("{}#{}", uri!(thread(post.board(), post.thread())), post.id())) { (">>") }
-                    a href="#" onclick=(format!("reply_to({}); event.preventDefault();", post.id())) { (post.id()) }
+                    a href=(format!("{}#{}", uri!(thread(post.board(), post.thread(), _)), post.id())) { (">>") }
+                    a href=(format!("{}#post", uri!(thread(post.board(), post.thread(), Some(post.id())))))
+                      onclick=(format!("reply_to({}); event.preventDefault();", post.id())) {
+                        @if listing && CONFIG.prefix_post_ids {
+                            (format!("/{}/{}", post.board(), post.id()))
+                        } @else {
+                            (post.id())
+                        }
+                    }
                 }
                 .timestamp {
                     @let time = post.posted_at().assume_utc();
                     time datetime=(time.to_string()) { (time.format("%Y-%m-%d %H:%M:%S")) }
                 }
+                details.report {
+                    summary { "Report" }
+                    form action=(uri!(report).to_string()) method="post" {
+                        input type="hidden" name="board" value=(post.board());
+                        input type="hidden" name="post" value=(post.id());
+                        input type="text" name="reason" maxlength="200" placeholder="Reason";
+                        input type="submit" value="Report";
+                    }
+                }
+                @if viewer.mod_tools {
+                    a.ban href=(uri!(admin::bans(Some(post.board()), Some(post.id()))).to_string()) { "Ban" }
+                }
             }
-            .content {
-                @if let Some(img) = post.image() {
-                    .image {
-                        a href=(format!("/images/{img}")) {
-                            img src=(format!("/thumbs/{img}.png"));
+            @if post.image().is_some() || post.has_content() {
+                .content {
+                    @if post.image().is_some() {
+                        .images {
+                            @for img in images.attached.get(&post.id()).into_iter().flatten() {
+                                (image_body(*img, post, board, images).await?)
+                            }
+                        }
+                    }
+                    @if post.has_content() {
+                        .text data-overflow=[overflow.then(|| "true")] { (post.html_content()) }
+                        @if overflow {
+                            .show-more {
+                                a href=(format!("{}#{}", uri!(thread(post.board(), post.thread(), _)), post.id())) {
+                                    "Post too long, view the full text"
+                                }
+                            }
                         }
                     }
                 }
-                .text { (post.html_content()) }
             }
-            .replies {
-                @for reply in post.replies(pool)
-                                  .await?
-                                  .into_iter()
-                                  .map(|r| html! {
-                                      a href=(format!("{}#{}", uri!(thread(r.board(), r.thread())), r.id())) { (">>")(r.id()) }
-                                    })
-                                  .intersperse(maud::PreEscaped(", ".to_string())) {
-                    (reply)
+            @if board.backlinks() {
+                .replies {
+                    (reply_links(replies, 1, viewer, pool).await?)
                 }
             }
         }
     })
 }
 
-fn post_form(board: &str, thread: Option<i32>, captcha: Option<&str>) -> Markup {
+/// Whether post text is too long to show in full under `max` characters.
+fn overflows(content: Option<&str>, max: usize) -> bool {
+    content.map_or(false, |c| c.chars().count() > max)
+}
+
+/// Render one of `post`'s images, or a player for a video.
+async fn image_body(
+    img: Uuid,
+    post: &Post,
+    board: &Board,
+    images: &PageImages,
+) -> Result<Markup, Error> {
+    let media = images.media.get(&img);
+    let thumb = Image::thumb_src_for(img, media.map_or("png", ImageMedia::thumb_ext)).await?;
+    Ok(html! {
+        .image {
+            @if let Some(video) = media.and_then(ImageMedia::media_type).filter(|t| t.starts_with("video/")) {
+                video controls preload="none" poster=(thumb) {
+                    source src=(Image::src(img)) type=(video);
+                }
+            } @else {
+                a href=(Image::src(img)) {
+                    img src=(thumb);
+                }
+            }
+            @if board.repost_notice() {
+                @let earlier: Vec<&ImageUse> = images
+                    .uses
+                    .get(&img)
+                    .into_iter()
+                    .flatten()
+                    .take_while(|p| p.posted_at() < post.posted_at())
+                    .collect();
+                @if !earlier.is_empty() {
+                    .repost {
+                        @if earlier.len() == 1 {
+                            "Posted once before: "
+                        } @else {
+                            (format!("Posted {} times before: ", earlier.len()))
+                        }
+                        // Only the latest few are linked, popular images can have a lot.
+                        @let skipped = earlier.len().saturating_sub(REPOST_LINKS);
+                        @if skipped > 0 { "…, " }
+                        @for (i, p) in earlier[skipped..].iter().enumerate() {
+                            @if i > 0 { ", " }
+                            a href=(format!("{}#{}", uri!(thread(p.board(), p.thread(), _)), p.id())) {
+                                (format!(">>/{}/{}", p.board(), p.id()))
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Links to `replies`, each followed by the replies to it in turn, down to `REPLY_DEPTH` levels.
+/// Past that only flat links are rendered, which keeps heavily cross-linked threads cheap.
+fn reply_links<'a>(
+    replies: Vec<Reply>,
+    depth: usize,
+    viewer: &'a Viewer,
+    pool: &'a PgPool,
+) -> Pin<Box<dyn Future<Output = Result<Markup, Error>> + Send + 'a>> {
+    Box::pin(async move {
+        let mut links = Vec::with_capacity(replies.len());
+        for r in replies {
+            let nested = if depth < CONFIG.reply_depth {
+                r.replies(pool).await?
+            } else {
+                Vec::new()
+            };
+            let nested = if nested.is_empty() {
+                None
+            } else {
+                Some(reply_links(nested, depth + 1, viewer, pool).await?)
+            };
+            links.push(html! {
+                a href=(format!("{}#{}", uri!(thread(r.board(), r.thread(), _)), r.id())) { (">>")(r.id()) }
+                @if viewer.owns(r.poster()) {
+                    " (You)"
+                }
+                @if let Some(nested) = nested {
+                    " (" (nested) ")"
+                }
+            });
+        }
+        Ok(html! {
+            @for link in links.into_iter().intersperse(PreEscaped(", ".to_string())) {
+                (link)
+            }
+        })
+    })
+}
+
+fn post_form(
+    board: &Board,
+    thread: Option<i32>,
+    reply_to: Option<i32>,
+    captcha: Option<&str>,
+) -> Markup {
     html! {
         .post-form {
             form id="post" action=(uri!(create_post).to_string()) method="post" enctype="multipart/form-data" {
@@ -211,30 +1263,44 @@ fn post_form(board: &str, thread: Option<i32>, captcha: Option<&str>) -> Markup
                     tbody {
                         tr {
                             td { label for="author" { "Name" } }
-                            td { input type="text" name="author"; }
+                            td { input type="text" name="author" id="author"; }
                         }
                         tr {
                             td { label for="title" { "Title" } }
-                            td { input type="text" name="title"; }
+                            td { input type="text" name="title" id="title"; }
                         }
-                        tr {
-                            td { label for="email" { "Email" }  }
-                            td { input type="text" name="email";  }
+                        @if !board.hide_email() {
+                            tr {
+                                td { label for="email" { "Email" }  }
+                                td { input type="text" name="email" id="email";  }
+                            }
                         }
-                        tr {
-                            td { label for="image" { "Image" }  }
-                            td { input type="file" name="image" accept="image/png, image/jpeg";  }
+                        @if !board.text_only() {
+                            tr {
+                                td { label for="image" { "Image" }  }
+                                td { input type="file" name="image" id="image" multiple accept=(upload_accept(board));  }
+                            }
+                        }
+                        @if CONFIG.honeypot != Honeypot::Off {
+                            tr.website {
+                                td { label for=(CONFIG.honeypot_field) { "Website" } }
+                                td { input type="text" name=(CONFIG.honeypot_field) id=(CONFIG.honeypot_field) tabindex="-1" autocomplete="off"; }
+                            }
                         }
                         tr {
                             td { label for="sage" { "Sage" } }
                             td {
-                                input type="checkbox" name="sage";
+                                input type="checkbox" name="sage" id="sage";
                                 input type="submit";
                             }
                         }
                         tr {
                             td { label for="content" { "Content" } }
-                            td { textarea name="content" form="post" {} }
+                            td {
+                                textarea name="content" id="content" form="post" {
+                                    @if let Some(id) = reply_to { (format!(">>{id}\n")) }
+                                }
+                            }
                         }
 
                         @if let Some(captcha) = captcha {
@@ -246,7 +1312,7 @@ fn post_form(board: &str, thread: Option<i32>, captcha: Option<&str>) -> Markup
                             }
                             tr {
                                 td {}
-                                td { input type="text" name="captcha"; }
+                                td { input type="text" name="captcha" id="captcha" onchange="check_captcha(this)"; }
                             }
                         } @else {
                             tr {
@@ -257,7 +1323,7 @@ fn post_form(board: &str, thread: Option<i32>, captcha: Option<&str>) -> Markup
                             }
                         }
                     }
-                    input type="hidden" name="board" value=(board);
+                    input type="hidden" name="board" value=(board.name());
                     @if let Some(thread) = thread {
                         input type="hidden" name="thread" value=(thread);
                     }
@@ -266,3 +1332,108 @@ fn post_form(board: &str, thread: Option<i32>, captcha: Option<&str>) -> Markup
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn posted_ago(now: OffsetDateTime, secs: i64) -> Option<PrimitiveDateTime> {
+        let at = now - sqlx::types::time::Duration::seconds(secs);
+        Some(PrimitiveDateTime::new(at.date(), at.time()))
+    }
+
+    #[test]
+    fn first_post_has_no_cooldown() {
+        let now = OffsetDateTime::now_utc();
+        assert_eq!(cooldown_left(None, Duration::from_secs(15), now), None);
+    }
+
+    #[test]
+    fn rapid_second_post_waits_out_the_rest() {
+        let now = OffsetDateTime::now_utc();
+        let cooldown = Duration::from_secs(15);
+        assert_eq!(
+            cooldown_left(posted_ago(now, 0), cooldown, now),
+            Some(cooldown)
+        );
+        assert_eq!(
+            cooldown_left(posted_ago(now, 5), cooldown, now),
+            Some(Duration::from_secs(10))
+        );
+    }
+
+    #[test]
+    fn post_after_cooldown_goes_through() {
+        let now = OffsetDateTime::now_utc();
+        let cooldown = Duration::from_secs(15);
+        assert_eq!(cooldown_left(posted_ago(now, 15), cooldown, now), None);
+        assert_eq!(cooldown_left(posted_ago(now, 60), cooldown, now), None);
+    }
+
+    #[test]
+    fn browsers_get_redirected() {
+        assert!(!wants_json(None));
+        assert!(!wants_json(Some(&Accept::HTML)));
+    }
+
+    #[test]
+    fn json_clients_get_the_new_post() {
+        assert!(wants_json(Some(&Accept::JSON)));
+        let body = rocket::serde::json::to_string(&CreatedPost { id: 12, thread: 7 }).unwrap();
+        assert_eq!(body, r#"{"id":12,"thread":7}"#);
+    }
+
+    #[test]
+    fn short_text_is_not_truncated() {
+        assert!(matches!(truncate("hello", 5), Cow::Borrowed("hello")));
+        assert!(matches!(truncate("", 5), Cow::Borrowed("")));
+    }
+
+    #[test]
+    fn long_text_is_cut_at_a_char_boundary() {
+        assert_eq!(truncate("hello world", 5), "hello…");
+        assert_eq!(truncate("привет мир", 6), "привет…");
+        assert_eq!(truncate("🦀🦀🦀", 2), "🦀🦀…");
+    }
+
+    #[test]
+    fn truncated_text_drops_trailing_whitespace() {
+        assert_eq!(truncate("hello   world", 7), "hello…");
+    }
+
+    #[test]
+    fn page_titles_name_the_board() {
+        assert_eq!(page_title("b", "Random", 50), "/b/ - Random");
+        assert_eq!(
+            page_title("g", "Which editor do you use and why", 12),
+            "/g/ - Which editor…"
+        );
+    }
+
+    #[test]
+    fn posts_up_to_the_preview_length_are_shown_in_full() {
+        assert!(!overflows(None, 5));
+        assert!(!overflows(Some("hello"), 5));
+        assert!(!overflows(Some("привет"), 6));
+    }
+
+    #[test]
+    fn longer_posts_are_collapsed() {
+        assert!(overflows(Some("hello!"), 5));
+        assert!(overflows(Some("привет!"), 6));
+    }
+
+    #[test]
+    fn feed_text_is_xml_escaped() {
+        assert_eq!(
+            xml_escape(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn escaped_entities_are_not_left_ambiguous() {
+        assert_eq!(xml_escape("&lt;"), "&amp;lt;");
+        assert_eq!(xml_escape("plain text"), "plain text");
+    }
+}
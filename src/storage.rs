@@ -0,0 +1,164 @@
+use crate::{
+    config::{StorageBackend, CONFIG},
+    errors::Error,
+};
+use once_cell::sync::Lazy;
+use rocket::async_trait;
+use s3::{creds::Credentials, region::Region, Bucket};
+use std::path::PathBuf;
+
+/// The backend selected by `STORAGE_BACKEND`.
+pub static STORAGE: Lazy<Box<dyn Storage>> = Lazy::new(|| match CONFIG.storage_backend {
+    StorageBackend::Local => Box::new(LocalStorage),
+    StorageBackend::S3 => Box::new(S3Storage::from_config()),
+});
+
+/// The kinds of files that get stored, each kept apart from the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Folder {
+    Images,
+    Thumbs,
+}
+
+impl Folder {
+    fn name(self) -> &'static str {
+        match self {
+            Folder::Images => "images",
+            Folder::Thumbs => "thumbs",
+        }
+    }
+}
+
+/// Somewhere to keep uploads and their thumbnails.
+/// Files are named by the caller and never change once they're written.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn put(
+        &self,
+        folder: Folder,
+        name: &str,
+        content_type: &str,
+        data: &[u8],
+    ) -> Result<(), Error>;
+
+    async fn get(&self, folder: Folder, name: &str) -> Result<Vec<u8>, Error>;
+
+    async fn delete(&self, folder: Folder, name: &str) -> Result<(), Error>;
+
+    /// Where browsers can load the file from.
+    fn url(&self, folder: Folder, name: &str) -> String;
+}
+
+/// Keeps files in `IMAGES_DIR` and `THUMBS_DIR`, which are served by the app itself.
+pub struct LocalStorage;
+
+impl LocalStorage {
+    fn path(folder: Folder, name: &str) -> PathBuf {
+        match folder {
+            Folder::Images => CONFIG.images_dir.join(name),
+            Folder::Thumbs => CONFIG.thumbs_dir.join(name),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, folder: Folder, name: &str, _: &str, data: &[u8]) -> Result<(), Error> {
+        Ok(tokio::fs::write(Self::path(folder, name), data).await?)
+    }
+
+    async fn get(&self, folder: Folder, name: &str) -> Result<Vec<u8>, Error> {
+        Ok(tokio::fs::read(Self::path(folder, name)).await?)
+    }
+
+    async fn delete(&self, folder: Folder, name: &str) -> Result<(), Error> {
+        Ok(tokio::fs::remove_file(Self::path(folder, name)).await?)
+    }
+
+    fn url(&self, folder: Folder, name: &str) -> String {
+        format!("/{}/{name}", folder.name())
+    }
+}
+
+/// Keeps files in an S3 compatible bucket, under `images/` and `thumbs/`.
+/// The bucket has to be publicly readable at `S3_PUBLIC_URL`, since files are linked to directly.
+pub struct S3Storage {
+    bucket: Bucket,
+    public_url: String,
+}
+
+impl S3Storage {
+    fn from_config() -> Self {
+        fn required(value: &Option<String>, key: &str) -> String {
+            value
+                .clone()
+                .unwrap_or_else(|| panic!("{key} must be set when STORAGE_BACKEND is s3"))
+        }
+
+        let name = required(&CONFIG.s3_bucket, "S3_BUCKET");
+        let region = match &CONFIG.s3_endpoint {
+            Some(endpoint) => Region::Custom {
+                region: CONFIG.s3_region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => CONFIG
+                .s3_region
+                .parse()
+                .unwrap_or_else(|e| panic!("Invalid value for S3_REGION: {e:?}")),
+        };
+        let credentials = Credentials::new(
+            CONFIG.s3_access_key.as_deref(),
+            CONFIG.s3_secret_key.as_deref(),
+            None,
+            None,
+            None,
+        )
+        .expect("Couldn't load S3 credentials");
+        let bucket = Bucket::new(&name, region, credentials).expect("Couldn't set up S3 bucket");
+        // Most self-hosted S3 implementations only understand path-style requests.
+        let bucket = if CONFIG.s3_endpoint.is_some() {
+            bucket.with_path_style()
+        } else {
+            bucket
+        };
+        let public_url = required(&CONFIG.s3_public_url, "S3_PUBLIC_URL");
+        Self {
+            bucket,
+            public_url: public_url.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    fn key(folder: Folder, name: &str) -> String {
+        format!("{}/{name}", folder.name())
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(
+        &self,
+        folder: Folder,
+        name: &str,
+        content_type: &str,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        self.bucket
+            .put_object_with_content_type(Self::key(folder, name), data, content_type)
+            .await?;
+        Ok(())
+    }
+
+    async fn get(&self, folder: Folder, name: &str) -> Result<Vec<u8>, Error> {
+        let response = self.bucket.get_object(Self::key(folder, name)).await?;
+        Ok(response.bytes().to_vec())
+    }
+
+    async fn delete(&self, folder: Folder, name: &str) -> Result<(), Error> {
+        self.bucket.delete_object(Self::key(folder, name)).await?;
+        Ok(())
+    }
+
+    fn url(&self, folder: Folder, name: &str) -> String {
+        format!("{}/{}", self.public_url, Self::key(folder, name))
+    }
+}
@@ -18,6 +18,14 @@ function ready() {
 }
 
 function reply_to(id) {
-    const textarea = document.querySelector('#post > table > tbody > tr:nth-child(6) > td:nth-child(2) > textarea');
+    const textarea = document.querySelector('#post textarea[name=content]');
     textarea.value += ' >>' + id;
 }
+
+async function check_captcha(input) {
+    const body = new FormData();
+    body.append('captcha', input.value);
+    const res = await fetch('/captcha/check', { method: 'POST', body });
+    input.setCustomValidity(res.status === 422 ? 'Wrong captcha' : '');
+    input.reportValidity();
+}